@@ -43,10 +43,13 @@ pub struct FilesystemStatus {
     pub quota_total: i64,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// Status of a file upload, also given by the `upload-offset` header,
+/// as `HEAD` responses have no body.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 #[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[cfg_attr(feature = "rkyv", derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize))]
 #[cfg_attr(feature = "rkyv", archive(check_bytes))]
+#[serde(default)]
 pub struct FileStatus {
     pub complete: u32,
     pub upload_offset: u64,
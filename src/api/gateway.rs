@@ -14,6 +14,11 @@ pub struct GatewayQueryParams {
     /// Whether to compress individual messages
     #[serde(alias = "c")]
     pub compress: bool,
+
+    /// Whether to compress the whole connection as a single zlib stream, flushed after each message,
+    /// rather than compressing each message individually. Only used if `compress` is true.
+    #[serde(alias = "s")]
+    pub stream: bool,
 }
 
 impl Default for GatewayQueryParams {
@@ -21,6 +26,7 @@ impl Default for GatewayQueryParams {
         GatewayQueryParams {
             encoding: Encoding::default(),
             compress: default_compress(),
+            stream: false,
         }
     }
 }
@@ -0,0 +1,272 @@
+use reqwest::Url;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use super::{Client, ClientError};
+use crate::{
+    api::asset::AssetQuery,
+    driver::{normalize_base_url, DriverError, UrlError},
+    models::{File, ServerConfig, Snowflake},
+};
+
+/// Builds URLs for assets served by the CDN, see [`Client::cdn`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CdnUrls {
+    base: Url,
+    pub(super) camo: bool,
+}
+
+impl CdnUrls {
+    /// Uses the CDN domain given by the server config, over HTTPS if the server is `secure`
+    pub fn new(config: &ServerConfig) -> Result<Self, UrlError> {
+        let scheme = if config.secure { "https" } else { "http" };
+
+        Ok(CdnUrls::from_base(Url::parse(&format!("{scheme}://{}", config.cdn))?)?.with_camo(config.camo))
+    }
+
+    /// Uses the given base URL, which may include a sub-path.
+    ///
+    /// Returns [`UrlError`] if the URL is not a valid `http` or `https` URL, same as for the server base URL.
+    pub fn from_base(base: Url) -> Result<Self, UrlError> {
+        Ok(CdnUrls {
+            base: Url::parse(&normalize_base_url(base.as_str())?)?,
+            camo: false,
+        })
+    }
+
+    /// Sets whether embed media should be proxied through the camo route, see [`CdnUrls::embed_media`]
+    #[inline]
+    pub fn with_camo(mut self, camo: bool) -> Self {
+        self.camo = camo;
+        self
+    }
+
+    #[inline]
+    pub fn base(&self) -> &Url {
+        &self.base
+    }
+
+    pub fn user_avatar(&self, user_id: Snowflake, hash: &str, query: impl Into<AssetQuery>) -> Url {
+        self.build(&["user", &user_id.to_string(), "avatar", hash], Some(query.into()))
+    }
+
+    pub fn user_banner(&self, user_id: Snowflake, hash: &str, query: impl Into<AssetQuery>) -> Url {
+        self.build(&["user", &user_id.to_string(), "banner", hash], Some(query.into()))
+    }
+
+    pub fn party_avatar(&self, party_id: Snowflake, hash: &str, query: impl Into<AssetQuery>) -> Url {
+        self.build(&["party", &party_id.to_string(), "avatar", hash], Some(query.into()))
+    }
+
+    pub fn party_banner(&self, party_id: Snowflake, hash: &str, query: impl Into<AssetQuery>) -> Url {
+        self.build(&["party", &party_id.to_string(), "banner", hash], Some(query.into()))
+    }
+
+    pub fn room_avatar(&self, room_id: Snowflake, hash: &str, query: impl Into<AssetQuery>) -> Url {
+        self.build(&["room", &room_id.to_string(), "avatar", hash], Some(query.into()))
+    }
+
+    pub fn role_avatar(&self, role_id: Snowflake, hash: &str, query: impl Into<AssetQuery>) -> Url {
+        self.build(&["role", &role_id.to_string(), "avatar", hash], Some(query.into()))
+    }
+
+    pub fn emote(&self, emote_id: Snowflake, query: impl Into<AssetQuery>) -> Url {
+        self.build(&["emote", &emote_id.to_string()], Some(query.into()))
+    }
+
+    /// Original file of an attachment sent in the given room
+    pub fn attachment(&self, room_id: Snowflake, file: &File) -> Url {
+        self.build(
+            &["attachments", &room_id.to_string(), &file.id.to_string(), &file.filename],
+            None,
+        )
+    }
+
+    pub(super) fn build(&self, segments: &[&str], query: Option<AssetQuery>) -> Url {
+        let mut url = self.base.clone();
+
+        // http(s) URLs always have a path, as checked by `from_base`
+        url.path_segments_mut().expect("CDN base URL cannot be a base").pop_if_empty().extend(segments);
+
+        if let Some(query) = query {
+            let query = serde_urlencoded::to_string(query).expect("AssetQuery is always url-encodable");
+
+            url.set_query(Some(&query));
+        }
+
+        url
+    }
+}
+
+impl Client {
+    /// Returns a [`CdnUrls`] builder for the CDN given by the cached [`server_config`](Client::server_config)
+    pub async fn cdn(&self) -> Result<CdnUrls, ClientError> {
+        let config = self.server_config().await?;
+
+        CdnUrls::new(&config).map_err(|e| ClientError::DriverError(DriverError::from(e)))
+    }
+
+    /// Downloads the given URL into `out`, resuming from `offset` if non-zero,
+    /// assuming the first `offset` bytes have already been written.
+    ///
+    /// Returns the total number of bytes downloaded, including `offset`. The download may be
+    /// resumed from that many bytes if interrupted, or if the body length did not match `Content-Length`.
+    pub async fn download(&self, url: Url, offset: u64, out: impl AsyncWrite) -> Result<u64, ClientError> {
+        let mut out = std::pin::pin!(out);
+
+        let mut download = self.driver().download(url, offset).await?;
+        let mut written = download.offset();
+
+        while let Some(chunk) = download.chunk().await? {
+            out.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+
+        out.flush().await?;
+
+        if let Some(expected) = download.total_len() {
+            if written != expected {
+                return Err(DriverError::ContentLengthMismatch {
+                    expected,
+                    received: written,
+                }
+                .into());
+            }
+        }
+
+        Ok(written)
+    }
+
+    /// Downloads the given URL to the end of `file`, resuming after any existing contents.
+    ///
+    /// Returns the full length of the file after downloading.
+    #[cfg(feature = "fs")]
+    pub async fn download_file(&self, url: Url, file: &mut tokio::fs::File) -> Result<u64, ClientError> {
+        use tokio::io::AsyncSeekExt;
+
+        let meta = file.metadata().await?;
+
+        if !meta.is_file() {
+            return Err(ClientError::NotAFile);
+        }
+
+        let offset = file.seek(std::io::SeekFrom::End(0)).await?;
+
+        self.download(url, offset, file).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{HeaderName, HeaderValue, StatusCode};
+
+    use super::*;
+
+    use crate::{
+        driver::{
+            mock::{MockResponse, MockTransport},
+            CommandInfo,
+        },
+        models::AssetFlags,
+    };
+
+    #[test]
+    fn test_cdn_urls() {
+        let cdn = CdnUrls::from_base(Url::parse("https://cdn.lantern.invalid").unwrap()).unwrap();
+
+        let id: Snowflake = "1".parse().unwrap();
+
+        let flags =
+            AssetFlags::empty().with_quality(80).with_alpha(true).with_animated(true).with_formats(AssetFlags::FORMAT_PNG);
+
+        assert_eq!(
+            cdn.user_avatar(id, "abc", flags).as_str(),
+            format!("https://cdn.lantern.invalid/user/1/avatar/abc?flags={}", flags.bits()),
+        );
+
+        let query = AssetQuery::HumanReadable {
+            quality: 90,
+            animated: false,
+            with_alpha: false,
+            ext: Some("jpg".into()),
+        };
+
+        assert_eq!(
+            cdn.emote(id, query).as_str(),
+            "https://cdn.lantern.invalid/emote/1?quality=90&animated=false&with_alpha=false&ext=jpg"
+        );
+
+        let file = File {
+            id,
+            filename: "some file?.txt".into(),
+            size: 0,
+            mime: None,
+            width: None,
+            height: None,
+            preview: None,
+        };
+
+        assert_eq!(
+            cdn.attachment(id, &file).as_str(),
+            "https://cdn.lantern.invalid/attachments/1/1/some%20file%3F.txt"
+        );
+
+        let cdn = CdnUrls::from_base(Url::parse("https://lantern.invalid/cdn/").unwrap()).unwrap();
+        assert_eq!(
+            cdn.emote(id, flags).as_str(),
+            format!("https://lantern.invalid/cdn/emote/1?flags={}", flags.bits())
+        );
+
+        for base in ["mailto:cdn@lantern.invalid", "data:text/plain,cdn"] {
+            assert!(CdnUrls::from_base(Url::parse(base).unwrap()).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_download_resume() {
+        let mock = std::sync::Arc::new(MockTransport::default());
+        let client = Client::from_transport(mock.clone(), "https://lantern.invalid").unwrap();
+
+        let url = Url::parse("https://cdn.lantern.invalid/file").unwrap();
+        let data: Vec<u8> = (0..32).collect();
+
+        let content_length = HeaderName::from_static("content-length");
+
+        // connection dropped after 6 of the remaining 22 bytes
+        mock.respond_raw(
+            CommandInfo::DOWNLOAD.name,
+            MockResponse::new(StatusCode::PARTIAL_CONTENT)
+                .with_body(data[10..16].to_vec())
+                .with_header(content_length.clone(), HeaderValue::from(22))
+                .with_header(
+                    HeaderName::from_static("content-range"),
+                    HeaderValue::from_static("bytes 10-31/32"),
+                ),
+        )
+        // then the server ignores the range request, so the existing bytes are skipped
+        .respond_raw(
+            CommandInfo::DOWNLOAD.name,
+            MockResponse::new(StatusCode::OK)
+                .with_body(data.clone())
+                .with_header(content_length, HeaderValue::from(data.len())),
+        );
+
+        let mut out = data[..10].to_vec();
+
+        let err = client.download(url.clone(), 10, &mut out).await.unwrap_err();
+        assert!(matches!(
+            err,
+            ClientError::DriverError(DriverError::ContentLengthMismatch {
+                expected: 22,
+                received: 6
+            })
+        ));
+        assert_eq!(out, data[..16]);
+
+        assert_eq!(client.download(url, 16, &mut out).await.unwrap(), 32);
+        assert_eq!(out, data);
+
+        let requests = mock.requests();
+        assert_eq!(requests[0].headers.get("range").unwrap(), "bytes=10-");
+        assert_eq!(requests[1].headers.get("range").unwrap(), "bytes=16-");
+    }
+}
@@ -0,0 +1,103 @@
+//! Embed media URLs, proxied through the CDN's camo route if enabled by [`ServerConfig::camo`](crate::models::ServerConfig::camo)
+//!
+//! Camo URLs take the form `{cdn}/camo/{base64_url}/{signature}`, where `base64_url` is the original URL
+//! encoded as URL-safe Base64 without padding, and `signature` is the [`UrlSignature`] given with the media.
+
+use base64::engine::{general_purpose::URL_SAFE_NO_PAD, Engine};
+use reqwest::Url;
+
+use super::CdnUrls;
+use crate::models::embed::{Embed, EmbedMedia, UrlSignature};
+
+/// Where in an embed a media entry was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EmbedMediaKind {
+    Image,
+    Thumbnail,
+    Video,
+}
+
+/// Resolved URL of an embed media entry, see [`CdnUrls::embed_media_urls`]
+#[derive(Debug, Clone)]
+pub struct EmbedMediaUrl<'a> {
+    /// Index of the embed within the given embeds
+    pub index: usize,
+    pub kind: EmbedMediaKind,
+    pub media: &'a EmbedMedia,
+    pub url: Url,
+}
+
+impl CdnUrls {
+    /// Proxies the given URL through the camo route, regardless of whether camo is enabled
+    pub fn camo(&self, url: &str, signature: &UrlSignature) -> Url {
+        let signature: &str = signature;
+
+        self.build(&["camo", &URL_SAFE_NO_PAD.encode(url), signature], None)
+    }
+
+    /// Resolves the URL to fetch the given media from, through camo if enabled and the media is signed.
+    pub fn embed_media(&self, media: &EmbedMedia) -> Result<Url, url::ParseError> {
+        match media.signature {
+            Some(ref signature) if self.camo => Ok(self.camo(&media.url, signature)),
+            _ => Url::parse(&media.url),
+        }
+    }
+
+    /// Resolves the URLs of all images, thumbnails and videos in the given embeds, such as [`Message::embeds`](crate::models::Message::embeds).
+    ///
+    /// Media with invalid URLs are skipped.
+    pub fn embed_media_urls<'a>(&self, embeds: &'a [Embed]) -> Vec<EmbedMediaUrl<'a>> {
+        let mut urls = Vec::new();
+
+        for (index, embed) in embeds.iter().enumerate() {
+            let Embed::V1(embed) = embed;
+
+            let media = [
+                (EmbedMediaKind::Image, &embed.img),
+                (EmbedMediaKind::Thumbnail, &embed.thumb),
+                (EmbedMediaKind::Video, &embed.video),
+            ];
+
+            for (kind, media) in media {
+                if let Some(media) = media {
+                    if let Ok(url) = self.embed_media(media) {
+                        urls.push(EmbedMediaUrl { index, kind, media, url });
+                    }
+                }
+            }
+        }
+
+        urls
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_camo_urls() {
+        let cdn = CdnUrls::from_base(Url::parse("https://cdn.lantern.invalid").unwrap()).unwrap();
+
+        let media: EmbedMedia =
+            serde_json::from_str(r#"{"u":"https://example.com/image.png?size=large","s":"Y2Ftby1zaWduYXR1cmUtdGVzdA"}"#).unwrap();
+
+        // camo disabled
+        assert_eq!(
+            cdn.embed_media(&media).unwrap().as_str(),
+            "https://example.com/image.png?size=large"
+        );
+
+        let cdn = cdn.with_camo(true);
+
+        assert_eq!(
+            cdn.embed_media(&media).unwrap().as_str(),
+            "https://cdn.lantern.invalid/camo/aHR0cHM6Ly9leGFtcGxlLmNvbS9pbWFnZS5wbmc_c2l6ZT1sYXJnZQ/Y2Ftby1zaWduYXR1cmUtdGVzdA"
+        );
+
+        // unsigned media is never proxied
+        let media: EmbedMedia = serde_json::from_str(r#"{"u":"https://example.com/image.png"}"#).unwrap();
+
+        assert_eq!(cdn.embed_media(&media).unwrap().as_str(), "https://example.com/image.png");
+    }
+}
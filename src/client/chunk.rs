@@ -0,0 +1,177 @@
+use std::time::Duration;
+
+use crate::models::ServerLimits;
+
+/// Chunk sizes used for file uploads, see [`Client::set_chunk_config`](super::Client::set_chunk_config)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChunkConfig {
+    /// Size of the first chunk, in bytes
+    pub initial: usize,
+
+    /// Smallest chunk size the upload may adapt down to
+    pub min: usize,
+
+    /// Largest chunk size the upload may adapt up to, further limited by the server's `max_upload_size`
+    pub max: usize,
+
+    /// Target time taken to upload each chunk. Chunk sizes are adapted to the observed throughput
+    /// to approach this, or stay fixed at `initial` if `None`.
+    pub target_duration: Option<Duration>,
+}
+
+impl ChunkConfig {
+    /// Default chunk configuration
+    ///
+    /// ```ignore
+    /// ChunkConfig {
+    ///     initial: 8MiB,
+    ///     min: 256KiB,
+    ///     max: 64MiB,
+    ///     target_duration: Some(5s),
+    /// }
+    /// ```
+    pub const DEFAULT: ChunkConfig = ChunkConfig {
+        initial: 1024 * 1024 * 8,
+        min: 1024 * 256,
+        max: 1024 * 1024 * 64,
+        target_duration: Some(Duration::from_secs(5)),
+    };
+
+    /// Fixed chunk size, without adapting to throughput
+    pub const fn fixed(size: usize) -> Self {
+        ChunkConfig {
+            initial: size,
+            min: size,
+            max: size,
+            target_duration: None,
+        }
+    }
+}
+
+impl Default for ChunkConfig {
+    #[inline]
+    fn default() -> Self {
+        ChunkConfig::DEFAULT
+    }
+}
+
+/// Chunks are kept to multiples of this, other than the last
+const CHUNK_ALIGN: usize = 1024 * 64;
+
+/// Tracks the chunk size for a single upload, adapting it to observed throughput
+pub(crate) struct ChunkSizer {
+    min: usize,
+    max: usize,
+    target_duration: Option<Duration>,
+    current: usize,
+}
+
+impl ChunkSizer {
+    pub fn new(config: ChunkConfig, limits: Option<&ServerLimits>) -> Self {
+        let mut max = config.max.max(1);
+
+        if let Some(limits) = limits {
+            max = max.min(usize::try_from(limits.max_upload_size).unwrap_or(usize::MAX).max(1));
+        }
+
+        let min = config.min.clamp(1, max);
+
+        ChunkSizer {
+            min,
+            max,
+            target_duration: config.target_duration,
+            current: config.initial.clamp(min, max),
+        }
+    }
+
+    #[inline]
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Updates the chunk size after a chunk of `len` bytes took `elapsed` to upload.
+    ///
+    /// The chunk size changes by at most a factor of two each time, to avoid overreacting to a single slow or fast chunk.
+    pub fn update(&mut self, len: usize, elapsed: Duration) {
+        let target = match self.target_duration {
+            Some(target) => target,
+            None => return,
+        };
+
+        // only full chunks are representative
+        if len < self.current {
+            return;
+        }
+
+        let throughput = len as f64 / elapsed.as_secs_f64().max(0.001);
+        let ideal = (throughput * target.as_secs_f64()) as usize;
+
+        let mut next = ideal.clamp(self.current / 2, self.current.saturating_mul(2));
+
+        if next >= CHUNK_ALIGN {
+            next -= next % CHUNK_ALIGN;
+        }
+
+        self.current = next.clamp(self.min, self.max);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: usize = 1024 * 1024;
+
+    #[test]
+    fn test_adaptive_chunk_size() {
+        let mut sizer = ChunkSizer::new(ChunkConfig::DEFAULT, None);
+        assert_eq!(sizer.current(), 8 * MIB);
+
+        // fast link, 8MiB/s, grows towards 40MiB but at most doubles
+        sizer.update(8 * MIB, Duration::from_secs(1));
+        assert_eq!(sizer.current(), 16 * MIB);
+
+        sizer.update(16 * MIB, Duration::from_secs(2));
+        assert_eq!(sizer.current(), 32 * MIB);
+
+        sizer.update(32 * MIB, Duration::from_secs(4));
+        assert_eq!(sizer.current(), 40 * MIB);
+
+        // partial final chunks are ignored
+        sizer.update(MIB, Duration::from_secs(100));
+        assert_eq!(sizer.current(), 40 * MIB);
+
+        // slow link, 25KiB/s
+        for _ in 0..10 {
+            let len = sizer.current();
+            sizer.update(len, Duration::from_secs_f64(len as f64 / (25.0 * 1024.0)));
+        }
+
+        assert_eq!(sizer.current(), ChunkConfig::DEFAULT.min);
+    }
+
+    #[test]
+    fn test_chunk_size_limits() {
+        let limits = ServerLimits {
+            max_upload_size: 4 * MIB as u64,
+            max_avatar_size: 0,
+            max_banner_size: 0,
+            max_avatar_pixels: 0,
+            max_banner_pixels: 0,
+            avatar_width: 0,
+            banner_width: 0,
+            banner_height: 0,
+        };
+
+        let mut sizer = ChunkSizer::new(ChunkConfig::DEFAULT, Some(&limits));
+        assert_eq!(sizer.current(), 4 * MIB);
+
+        sizer.update(4 * MIB, Duration::from_millis(1));
+        assert_eq!(sizer.current(), 4 * MIB);
+
+        let mut sizer = ChunkSizer::new(ChunkConfig::fixed(MIB), None);
+
+        sizer.update(MIB, Duration::from_secs(100));
+        assert_eq!(sizer.current(), MIB);
+    }
+}
@@ -1,5 +1,7 @@
 use crate::driver::DriverError;
 
+use super::{UploadLimitError, UploadState};
+
 #[derive(Debug, thiserror::Error)]
 pub enum ClientError {
     #[error("IO Error: {0}")]
@@ -19,6 +21,15 @@ pub enum ClientError {
 
     #[error("Not a file")]
     NotAFile,
+
+    #[error("Upload source does not match the upload state")]
+    UploadSourceMismatch,
+
+    #[error("Upload Limit Exceeded: {0}")]
+    UploadLimitExceeded(#[from] UploadLimitError),
+
+    #[error("Upload Cancelled")]
+    UploadCancelled(UploadState),
 }
 
 impl From<DriverError> for ClientError {
@@ -1,13 +1,45 @@
-use bytes::BytesMut;
+use std::{io::SeekFrom, pin::Pin, sync::Arc};
+
+use bytes::{Bytes, BytesMut};
 use smol_str::SmolStr;
-use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
 
-use super::{Client, ClientError};
+use super::{
+    chunk::ChunkSizer,
+    limits::check_upload,
+    upload::{Throughput, UploadCancel, UploadEvent},
+    Client, ClientError, UploadKind,
+};
 use crate::{
-    api::commands::file::{CreateFile, CreateFileBody},
+    api::{
+        commands::file::{CreateFile, CreateFileBody, GetFilesystemStatus},
+        error::ApiErrorCode,
+    },
+    driver::{Driver, DriverError},
     models::Snowflake,
 };
 
+/// Persistable state of a resumable upload, see [`Client::resume_upload`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadState {
+    pub file_id: Snowflake,
+
+    /// Total size of the file, in bytes
+    pub size: u64,
+
+    /// Number of bytes acknowledged by the server
+    pub offset: u64,
+
+    /// CRC32 of the first `offset` bytes, used to verify the source is unchanged when resuming
+    pub crc32: u32,
+}
+
+impl UploadState {
+    pub fn is_complete(&self) -> bool {
+        self.offset >= self.size
+    }
+}
+
 impl Client {
     /// Upload a plain file from its handle
     ///
@@ -42,31 +74,202 @@ impl Client {
         self.upload_stream(meta, file, progress).await
     }
 
+    /// Upload a media file from its handle
+    ///
+    /// The MIME type and dimensions are detected from the file contents, and a blurhash
+    /// preview is generated for images small enough to decode, see [`media`](super::media).
+    ///
+    /// Images are decoded on a blocking thread, see [`spawn_blocking`](tokio::task::spawn_blocking).
+    #[cfg(all(feature = "media", feature = "fs"))]
+    pub async fn upload_media_file(
+        &self,
+        filename: impl Into<SmolStr>,
+        file: &mut tokio::fs::File,
+        progress: impl FnMut(u64, u64),
+    ) -> Result<Snowflake, ClientError> {
+        use super::media::{MediaInfo, HEADER_LEN, MAX_PREVIEW_FILE_SIZE};
+
+        let meta = file.metadata().await?;
+
+        if !meta.is_file() {
+            return Err(ClientError::NotAFile);
+        }
+
+        let size = match i32::try_from(meta.len()) {
+            Ok(size) => size,
+            Err(_) => return Err(ClientError::FileTooLarge),
+        };
+
+        let mut data = Vec::with_capacity(HEADER_LEN);
+        (&mut *file).take(HEADER_LEN as u64).read_to_end(&mut data).await?;
+
+        let mut info = MediaInfo::probe(&data);
+
+        if info.can_preview() && meta.len() <= MAX_PREVIEW_FILE_SIZE {
+            file.read_to_end(&mut data).await?;
+
+            info = tokio::task::spawn_blocking(move || {
+                info.generate_preview(&data);
+                info
+            })
+            .await
+            .map_err(std::io::Error::from)?;
+        }
+
+        file.seek(SeekFrom::Start(0)).await?;
+
+        let meta = CreateFileBody {
+            filename: filename.into(),
+            size,
+            width: info.width,
+            height: info.height,
+            mime: info.mime,
+            preview: info.preview,
+        };
+
+        self.upload_stream(meta, file, progress).await
+    }
+
     /// Uploads a file stream in chunks
+    ///
+    /// Failed chunks are retried as per the [`RetryPolicy`](crate::driver::RetryPolicy),
+    /// but if the upload fails entirely it must be restarted. See [`resume_upload`](Client::resume_upload)
+    /// for uploads that can be continued later.
     pub async fn upload_stream(
         &self,
         meta: CreateFileBody,
         stream: impl AsyncRead,
+        progress: impl FnMut(u64, u64),
+    ) -> Result<Snowflake, ClientError> {
+        self.upload_stream_as(UploadKind::File, meta, stream, progress).await
+    }
+
+    /// Same as [`upload_stream`](Client::upload_stream), but checks the limits for the given [`UploadKind`],
+    /// such as for avatars or banners.
+    pub async fn upload_stream_as(
+        &self,
+        kind: UploadKind,
+        meta: CreateFileBody,
+        stream: impl AsyncRead,
         mut progress: impl FnMut(u64, u64),
     ) -> Result<Snowflake, ClientError> {
-        let file_size = meta.size as u64;
+        let mut state = self.begin_upload_as(kind, meta).await?;
+
+        let mut events = |event| {
+            if let UploadEvent::ChunkAcknowledged { state, .. } = event {
+                progress(state.offset, state.size);
+            }
+        };
+
+        self.upload_chunks(&mut state, std::pin::pin!(stream), &mut events, None).await?;
+
+        Ok(state.file_id)
+    }
+
+    /// Creates a new file, returning the initial [`UploadState`] to be given to [`resume_upload`](Client::resume_upload).
+    pub async fn begin_upload(&self, meta: CreateFileBody) -> Result<UploadState, ClientError> {
+        self.begin_upload_as(UploadKind::File, meta).await
+    }
+
+    /// Same as [`begin_upload`](Client::begin_upload), but checks the limits for the given [`UploadKind`].
+    ///
+    /// Only the limits of an already cached [`server_config`](Client::server_config) are checked, without any extra requests,
+    /// so use [`check_upload`](Client::check_upload) beforehand to also check the storage quota.
+    pub async fn begin_upload_as(&self, kind: UploadKind, meta: CreateFileBody) -> Result<UploadState, ClientError> {
+        let config = self.0.server_config.load_full();
+
+        check_upload(&meta, kind, config.as_ref().map(|config| &config.limits), None)?;
+
+        let size = meta.size as u64;
         let file_id = self.driver().execute(CreateFile { body: meta }).await?;
 
-        // TODO: Retrieve chunk size from server? Or set it from Client?
-        const CHUNK_SIZE: usize = 1024 * 1024 * 8; // 8MiB
+        Ok(UploadState {
+            file_id,
+            size,
+            offset: 0,
+            crc32: 0,
+        })
+    }
 
-        let mut buffer = BytesMut::new();
-        let mut read = 0;
+    /// Checks the file against the server's upload limits and the user's storage quota,
+    /// returning [`ClientError::UploadLimitExceeded`] if it would be rejected.
+    pub async fn check_upload(&self, meta: &CreateFileBody, kind: UploadKind) -> Result<(), ClientError> {
+        let config = self.server_config().await?;
+        let quota = self.driver().execute(GetFilesystemStatus::new()).await?;
 
-        let mut stream = std::pin::pin!(stream);
+        Ok(check_upload(meta, kind, Some(&config.limits), Some(&quota))?)
+    }
+
+    /// Uploads the remainder of a file from a seekable source, such as the file the upload was started from.
+    ///
+    /// The server is queried for its current offset, and the source is verified against the checksum
+    /// in `state` before continuing. `progress` is given the updated state after each chunk,
+    /// which may be persisted to resume again after a crash or network failure.
+    pub async fn resume_upload(
+        &self,
+        state: &mut UploadState,
+        source: impl AsyncRead + AsyncSeek,
+        mut progress: impl FnMut(&UploadState),
+    ) -> Result<(), ClientError> {
+        let mut source = std::pin::pin!(source);
+
+        self.sync_upload(state, source.as_mut()).await?;
+
+        let mut events = |event| {
+            if let UploadEvent::ChunkAcknowledged { ref state, .. } = event {
+                progress(state);
+            }
+        };
+
+        self.upload_chunks(state, source, &mut events, None).await
+    }
+
+    /// Moves the source to the server's offset for the upload, verifying it along the way.
+    pub(super) async fn sync_upload<S: AsyncRead + AsyncSeek>(
+        &self,
+        state: &mut UploadState,
+        source: Pin<&mut S>,
+    ) -> Result<(), ClientError> {
+        let server_offset = self.driver().file_status(state.file_id).await?.upload_offset;
+
+        sync_source(state, source, server_offset).await
+    }
+
+    /// Uploads the stream from the current offset, stopping between chunks if cancelled.
+    pub(super) async fn upload_chunks<S: AsyncRead>(
+        &self,
+        state: &mut UploadState,
+        mut stream: Pin<&mut S>,
+        events: &mut dyn FnMut(UploadEvent),
+        cancel: Option<&UploadCancel>,
+    ) -> Result<(), ClientError> {
+        // server limits are only used to bound the chunk size, so it's fine to continue without them
+        let server_config = self.server_config().await.ok();
+
+        let mut sizer = ChunkSizer::new(self.chunk_config(), server_config.as_ref().map(|config| &config.limits));
+
+        let driver = self.driver();
+
+        let mut hasher = crc32fast::Hasher::new_with_initial(state.crc32);
+        let mut buffer = BytesMut::new();
+        let mut throughput = Throughput::default();
 
         loop {
-            // keep the buffer topped up at CHUNK_SIZE
-            buffer.reserve(CHUNK_SIZE - buffer.capacity());
+            // the last acknowledged chunk is always a valid place to resume from
+            if cancel.is_some_and(UploadCancel::is_cancelled) {
+                return Err(ClientError::UploadCancelled(*state));
+            }
+
+            let remaining = state.size.saturating_sub(state.offset);
+            let chunk_size = sizer.current().min(usize::try_from(remaining).unwrap_or(usize::MAX).max(1));
+
+            buffer.reserve(chunk_size);
 
             // fill buffer
-            while buffer.len() < buffer.capacity() {
-                if 0 == stream.read_buf(&mut buffer).await? {
+            while buffer.len() < chunk_size {
+                let mut limited = stream.as_mut().take((chunk_size - buffer.len()) as u64);
+
+                if 0 == limited.read_buf(&mut buffer).await? {
                     break;
                 }
             }
@@ -75,25 +278,295 @@ impl Client {
                 break;
             }
 
-            let offset = read;
+            let chunk = buffer.split().freeze();
 
-            read += buffer.len() as u64;
+            let start = tokio::time::Instant::now();
 
-            let new_offset = self.driver().patch_file(file_id, offset, buffer.split().freeze()).await?;
+            upload_chunk(&driver, state.file_id, state.offset, chunk.clone(), events).await?;
 
-            if new_offset != read {
-                return Err(
-                    std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Upload request returned unexpected offset").into(),
-                );
-            }
+            let elapsed = start.elapsed();
+
+            sizer.update(chunk.len(), elapsed);
+            throughput.update(chunk.len() as u64, elapsed);
+
+            hasher.update(&chunk);
+
+            state.offset += chunk.len() as u64;
+            state.crc32 = hasher.clone().finalize();
 
-            progress(read, file_size);
+            events(UploadEvent::ChunkAcknowledged {
+                state: *state,
+                bytes_per_second: throughput.bytes_per_second(),
+                eta: throughput.eta(state.size.saturating_sub(state.offset)),
+            });
         }
 
-        if file_size != read {
+        if state.size != state.offset {
             return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "File stream terminated too early").into());
         }
 
-        Ok(file_id)
+        Ok(())
+    }
+}
+
+fn unexpected_offset() -> ClientError {
+    std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Upload request returned unexpected offset").into()
+}
+
+/// Network failures, server errors and corrupted chunks are worth retrying
+fn is_retryable_chunk_error(err: &DriverError) -> bool {
+    match err {
+        DriverError::ReqwestError(e) => !e.is_builder(),
+        DriverError::GenericDriverError(status) => status.is_server_error() || *status == http::StatusCode::TOO_MANY_REQUESTS,
+        DriverError::ApiError(e) => matches!(
+            e.code,
+            // server errors that may not happen again, unlike encoding errors or unimplemented features
+            ApiErrorCode::ChecksumMismatch
+                | ApiErrorCode::DbError
+                | ApiErrorCode::JoinError
+                | ApiErrorCode::SemaphoreError
+                | ApiErrorCode::InternalError
+                | ApiErrorCode::IOError
+                | ApiErrorCode::RequestError
+        ),
+        _ => false,
+    }
+}
+
+/// Uploads a single chunk, retrying as per the [`RetryPolicy`](crate::driver::RetryPolicy).
+///
+/// Chunks are written at an explicit offset, so retrying is safe even though `PATCH` is not idempotent.
+/// After a failure, the server is checked for whether it received the chunk anyway.
+async fn upload_chunk(
+    driver: &Driver,
+    file_id: Snowflake,
+    offset: u64,
+    chunk: Bytes,
+    events: &mut dyn FnMut(UploadEvent),
+) -> Result<(), ClientError> {
+    let end = offset + chunk.len() as u64;
+
+    let mut attempts = 1;
+
+    loop {
+        match driver.patch_file(file_id, offset, chunk.clone()).await {
+            Ok(new_offset) if new_offset == end => return Ok(()),
+            Ok(_) => return Err(unexpected_offset()),
+            Err(e) if attempts < driver.retry.max_attempts && is_retryable_chunk_error(&e) => {
+                events(UploadEvent::Retried {
+                    offset,
+                    attempts,
+                    error: Arc::new(e),
+                });
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        tokio::time::sleep(driver.retry.delay(attempts, None)).await;
+
+        attempts += 1;
+
+        match driver.file_status(file_id).await {
+            Ok(status) if status.upload_offset == end => return Ok(()),
+            Ok(status) if status.upload_offset != offset => return Err(unexpected_offset()),
+            // if the status could not be retrieved either, retrying the chunk will fail in the same way
+            _ => {}
+        }
+    }
+}
+
+/// Verifies the source against the checksum for the last known offset, and moves it to the server's offset.
+async fn sync_source<S: AsyncRead + AsyncSeek>(
+    state: &mut UploadState,
+    mut source: Pin<&mut S>,
+    server_offset: u64,
+) -> Result<(), ClientError> {
+    if server_offset > state.size {
+        return Err(unexpected_offset());
+    }
+
+    source.as_mut().seek(SeekFrom::Start(0)).await?;
+
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0; 64 * 1024];
+    let mut pos = 0;
+    let mut crc32 = 0;
+
+    // one pass over the source, checking the checksum at each offset along the way
+    let mut stops = [state.offset, server_offset];
+    stops.sort_unstable();
+
+    for stop in stops {
+        while pos < stop {
+            let len = buffer.len().min((stop - pos) as usize);
+            let read = source.read(&mut buffer[..len]).await?;
+
+            if read == 0 {
+                return Err(ClientError::UploadSourceMismatch);
+            }
+
+            hasher.update(&buffer[..read]);
+            pos += read as u64;
+        }
+
+        let crc = hasher.clone().finalize();
+
+        if stop == state.offset && crc != state.crc32 {
+            return Err(ClientError::UploadSourceMismatch);
+        }
+
+        if stop == server_offset {
+            crc32 = crc;
+        }
+    }
+
+    source.seek(SeekFrom::Start(server_offset)).await?;
+
+    state.offset = server_offset;
+    state.crc32 = crc32;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use http::{HeaderName, HeaderValue, StatusCode};
+
+    use super::*;
+
+    use crate::{
+        api::commands::{config::GetServerConfig, file::GetFileStatus},
+        client::UploadLimitError,
+        driver::{
+            mock::{MockResponse, MockTransport},
+            CommandInfo,
+        },
+        models::{ServerConfig, ServerLimits},
+    };
+
+    fn upload_offset(offset: u64) -> MockResponse {
+        MockResponse::new(StatusCode::NO_CONTENT).with_header(HeaderName::from_static("upload-offset"), HeaderValue::from(offset))
+    }
+
+    #[test]
+    fn test_retryable_chunk_errors() {
+        let api_error = |code| {
+            DriverError::ApiError(crate::api::error::ApiError {
+                code,
+                message: "".into(),
+            })
+        };
+
+        assert!(is_retryable_chunk_error(&api_error(ApiErrorCode::ChecksumMismatch)));
+        assert!(is_retryable_chunk_error(&api_error(ApiErrorCode::DbError)));
+        assert!(!is_retryable_chunk_error(&api_error(ApiErrorCode::Unimplemented)));
+        assert!(!is_retryable_chunk_error(&api_error(ApiErrorCode::CborError)));
+        assert!(!is_retryable_chunk_error(&api_error(ApiErrorCode::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_begin_upload_limits() {
+        let mock = Arc::new(MockTransport::default());
+
+        let client = Client::from_transport(mock.clone(), "https://lantern.invalid").unwrap();
+        client.set_auth(Some("a".repeat(28).parse().unwrap())).unwrap();
+
+        mock.respond::<GetServerConfig>(MockResponse::json(&ServerConfig {
+            hcaptcha_sitekey: String::new(),
+            cdn: String::new(),
+            min_age: 0,
+            secure: true,
+            limits: ServerLimits {
+                max_upload_size: 16,
+                max_avatar_size: 0,
+                max_banner_size: 0,
+                max_avatar_pixels: 0,
+                max_banner_pixels: 0,
+                avatar_width: 0,
+                banner_width: 0,
+                banner_height: 0,
+            },
+            camo: false,
+        }))
+        .respond::<CreateFile>(MockResponse::json(&"1".parse::<Snowflake>().unwrap()));
+
+        let meta = || CreateFileBody {
+            filename: "test.bin".into(),
+            size: 32,
+            mime: None,
+            width: None,
+            height: None,
+            preview: None,
+        };
+
+        // nothing cached, so left to the server without any extra requests
+        client.begin_upload(meta()).await.unwrap();
+
+        let requests = mock.take_requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].is::<CreateFile>());
+
+        client.server_config().await.unwrap();
+        mock.take_requests();
+
+        let res = client.begin_upload(meta()).await;
+        assert!(matches!(
+            res,
+            Err(ClientError::UploadLimitExceeded(UploadLimitError::MaxUploadSize {
+                size: 32,
+                limit: 16
+            }))
+        ));
+        assert!(mock.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_resume_upload() {
+        let data: Vec<u8> = (0..32).collect();
+
+        let mock = Arc::new(MockTransport::default());
+
+        let client = Client::from_transport(mock.clone(), "https://lantern.invalid").unwrap();
+        client.set_auth(Some("a".repeat(28).parse().unwrap())).unwrap();
+
+        // last persisted state was at 8 bytes, but the server received the next chunk as well
+        let mut state = UploadState {
+            file_id: "1".parse().unwrap(),
+            size: data.len() as u64,
+            offset: 8,
+            crc32: crc32fast::hash(&data[..8]),
+        };
+
+        mock.respond::<GetFileStatus>(upload_offset(16));
+        mock.respond_raw(CommandInfo::PATCH_FILE.name, upload_offset(32));
+
+        let mut saved = Vec::new();
+
+        client.resume_upload(&mut state, std::io::Cursor::new(&data), |state| saved.push(*state)).await.unwrap();
+
+        assert!(state.is_complete());
+        assert_eq!(state.crc32, crc32fast::hash(&data));
+        assert_eq!(saved, [state]);
+
+        let patches: Vec<_> =
+            mock.take_requests().into_iter().filter(|req| req.command == CommandInfo::PATCH_FILE.name).collect();
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].headers.get("upload-offset").unwrap(), "16");
+        assert_eq!(patches[0].body.as_deref(), Some(&data[16..]));
+
+        // modified source
+        let mut state = UploadState {
+            offset: 8,
+            crc32: crc32fast::hash(&data[..8]),
+            ..state
+        };
+
+        let mut data = data;
+        data[0] = 255;
+
+        let res = client.resume_upload(&mut state, std::io::Cursor::new(&data), |_| {}).await;
+        assert!(matches!(res, Err(ClientError::UploadSourceMismatch)));
     }
 }
@@ -0,0 +1,184 @@
+use crate::{
+    api::commands::file::{CreateFileBody, FilesystemStatus},
+    models::ServerLimits,
+};
+
+/// What an upload will be used for, which determines the limits it must satisfy
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UploadKind {
+    /// Regular attachment
+    #[default]
+    File,
+
+    /// User or party avatar
+    Avatar,
+
+    /// User or party banner
+    Banner,
+}
+
+/// A server limit that an upload would exceed, found before uploading anything
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum UploadLimitError {
+    #[error("File size of {size} bytes exceeds the maximum upload size of {limit} bytes")]
+    MaxUploadSize { size: u64, limit: u64 },
+
+    #[error("File size of {size} bytes exceeds the remaining storage quota of {remaining} bytes", remaining = total.saturating_sub(*used).max(0))]
+    Quota { size: u64, used: i64, total: i64 },
+
+    #[error("{kind:?} size of {size} bytes exceeds the maximum of {limit} bytes")]
+    AssetSize { kind: UploadKind, size: u64, limit: u64 },
+
+    #[error("{kind:?} dimensions of {width}x{height} exceed the maximum of {limit} pixels")]
+    AssetPixels {
+        kind: UploadKind,
+        width: u32,
+        height: u32,
+        limit: u64,
+    },
+}
+
+impl UploadLimitError {
+    /// How far over the limit the upload is, in bytes or pixels
+    pub fn excess(&self) -> u64 {
+        match *self {
+            UploadLimitError::MaxUploadSize { size, limit } => size.saturating_sub(limit),
+            UploadLimitError::Quota { size, used, total } => {
+                (used.saturating_add(size.try_into().unwrap_or(i64::MAX)).saturating_sub(total)).max(0) as u64
+            }
+            UploadLimitError::AssetSize { size, limit, .. } => size.saturating_sub(limit),
+            UploadLimitError::AssetPixels {
+                width, height, limit, ..
+            } => (width as u64 * height as u64).saturating_sub(limit),
+        }
+    }
+}
+
+/// Checks the file against the server limits for the given kind of upload, and the user's storage quota,
+/// skipping either check if not known.
+pub(crate) fn check_upload(
+    meta: &CreateFileBody,
+    kind: UploadKind,
+    limits: Option<&ServerLimits>,
+    quota: Option<&FilesystemStatus>,
+) -> Result<(), UploadLimitError> {
+    let size = meta.size.max(0) as u64;
+
+    if let Some(limits) = limits {
+        if size > limits.max_upload_size {
+            return Err(UploadLimitError::MaxUploadSize {
+                size,
+                limit: limits.max_upload_size,
+            });
+        }
+    }
+
+    if let Some(&FilesystemStatus { quota_used, quota_total }) = quota {
+        if quota_used.saturating_add(size as i64) > quota_total {
+            return Err(UploadLimitError::Quota {
+                size,
+                used: quota_used,
+                total: quota_total,
+            });
+        }
+    }
+
+    let Some(limits) = limits else {
+        return Ok(());
+    };
+
+    let (max_size, max_pixels) = match kind {
+        UploadKind::File => return Ok(()),
+        UploadKind::Avatar => (limits.max_avatar_size, limits.max_avatar_pixels),
+        UploadKind::Banner => (limits.max_banner_size, limits.max_banner_pixels),
+    };
+
+    if size > max_size as u64 {
+        return Err(UploadLimitError::AssetSize {
+            kind,
+            size,
+            limit: max_size as u64,
+        });
+    }
+
+    if let (Some(width), Some(height)) = (meta.width, meta.height) {
+        let (width, height) = (width.max(0) as u32, height.max(0) as u32);
+
+        if width as u64 * height as u64 > max_pixels as u64 {
+            return Err(UploadLimitError::AssetPixels {
+                kind,
+                width,
+                height,
+                limit: max_pixels as u64,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upload_limits() {
+        let limits = ServerLimits {
+            max_upload_size: 1000,
+            max_avatar_size: 100,
+            max_banner_size: 200,
+            max_avatar_pixels: 64 * 64,
+            max_banner_pixels: 128 * 64,
+            avatar_width: 64,
+            banner_width: 128,
+            banner_height: 64,
+        };
+
+        let meta = |size, dims: Option<(i32, i32)>| CreateFileBody {
+            filename: "test.png".into(),
+            size,
+            mime: None,
+            width: dims.map(|d| d.0),
+            height: dims.map(|d| d.1),
+            preview: None,
+        };
+
+        let quota = FilesystemStatus {
+            quota_used: 900,
+            quota_total: 1200,
+        };
+
+        assert_eq!(check_upload(&meta(1000, None), UploadKind::File, Some(&limits), None), Ok(()));
+
+        let err = check_upload(&meta(1001, None), UploadKind::File, Some(&limits), None).unwrap_err();
+        assert_eq!(err, UploadLimitError::MaxUploadSize { size: 1001, limit: 1000 });
+        assert_eq!(err.excess(), 1);
+
+        let err = check_upload(&meta(350, None), UploadKind::File, Some(&limits), Some(&quota)).unwrap_err();
+        assert!(matches!(err, UploadLimitError::Quota { .. }));
+        assert_eq!(err.excess(), 50);
+        assert_eq!(
+            err.to_string(),
+            "File size of 350 bytes exceeds the remaining storage quota of 300 bytes"
+        );
+
+        let err = check_upload(&meta(150, None), UploadKind::Avatar, Some(&limits), Some(&quota)).unwrap_err();
+        assert_eq!(err.excess(), 50);
+
+        assert_eq!(
+            check_upload(&meta(150, Some((128, 64))), UploadKind::Banner, Some(&limits), None),
+            Ok(())
+        );
+
+        let err = check_upload(&meta(50, Some((65, 64))), UploadKind::Avatar, Some(&limits), None).unwrap_err();
+        assert!(matches!(err, UploadLimitError::AssetPixels { width: 65, .. }));
+        assert_eq!(err.excess(), 64);
+
+        // unknown limits are skipped
+        assert_eq!(
+            check_upload(&meta(5000, Some((65, 64))), UploadKind::Avatar, None, None),
+            Ok(())
+        );
+        assert!(check_upload(&meta(350, None), UploadKind::File, None, Some(&quota)).is_err());
+    }
+}
@@ -0,0 +1,256 @@
+//! Media detection for uploads, see [`Client::upload_media_file`]
+//!
+//! Image previews are standard [BlurHash](https://blurha.sh/) strings, stored in [`File::preview`](crate::models::File::preview).
+
+use image::{imageops::FilterType, DynamicImage};
+use imagesize::{Compression, ImageType};
+use smol_str::SmolStr;
+
+/// Number of bytes needed to reliably detect formats and dimensions
+pub(crate) const HEADER_LEN: usize = 64 * 1024;
+
+/// Images larger than this are not decoded for previews
+pub(crate) const MAX_PREVIEW_FILE_SIZE: u64 = 1024 * 1024 * 32;
+
+/// Basic media information detected from the file contents
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MediaInfo {
+    pub mime: Option<SmolStr>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub preview: Option<String>,
+}
+
+impl MediaInfo {
+    /// Detects the MIME type and dimensions from the first bytes of a file,
+    /// at least [`HEADER_LEN`] if available.
+    pub fn probe(header: &[u8]) -> MediaInfo {
+        let mut info = MediaInfo {
+            mime: sniff_mime(header).map(SmolStr::new_inline),
+            ..MediaInfo::default()
+        };
+
+        if let Ok(size) = imagesize::blob_size(header) {
+            info.width = i32::try_from(size.width).ok();
+            info.height = i32::try_from(size.height).ok();
+        }
+
+        info
+    }
+
+    /// Returns true if a preview can be generated for this type of file
+    pub fn can_preview(&self) -> bool {
+        matches!(
+            self.mime.as_deref(),
+            Some("image/png" | "image/jpeg" | "image/gif" | "image/webp")
+        )
+    }
+
+    /// Decodes the full image to generate a preview, also correcting the dimensions if needed.
+    ///
+    /// This is CPU-bound, and may take a while for large images.
+    pub fn generate_preview(&mut self, data: &[u8]) {
+        if !self.can_preview() {
+            return;
+        }
+
+        let image = match image::load_from_memory(data) {
+            Ok(image) => image,
+            Err(_) => return,
+        };
+
+        self.width = i32::try_from(image.width()).ok();
+        self.height = i32::try_from(image.height()).ok();
+        self.preview = Some(blurhash(&image));
+    }
+}
+
+/// Detects the MIME type of a file from its magic bytes
+pub fn sniff_mime(header: &[u8]) -> Option<&'static str> {
+    if let Ok(kind) = imagesize::image_type(header) {
+        return Some(match kind {
+            ImageType::Png => "image/png",
+            ImageType::Jpeg => "image/jpeg",
+            ImageType::Gif => "image/gif",
+            ImageType::Webp => "image/webp",
+            ImageType::Heif(Compression::Av1) => "image/avif",
+            ImageType::Heif(_) => "image/heif",
+            ImageType::Bmp => "image/bmp",
+            ImageType::Ico => "image/x-icon",
+            ImageType::Jxl => "image/jxl",
+            ImageType::Tiff => "image/tiff",
+            _ => return None,
+        });
+    }
+
+    None
+}
+
+/// Number of blurhash components along the larger dimension
+const COMPONENTS: u32 = 4;
+
+/// Images are downscaled to this before computing the blurhash, as the result would be the same
+const THUMBNAIL_SIZE: u32 = 32;
+
+/// Computes the [BlurHash](https://github.com/woltapp/blurhash/blob/master/Algorithm.md) of an image
+pub(crate) fn blurhash(image: &DynamicImage) -> String {
+    let image = image.resize(THUMBNAIL_SIZE, THUMBNAIL_SIZE, FilterType::Triangle).into_rgb8();
+
+    let (width, height) = image.dimensions();
+
+    // keep components roughly proportional to the aspect ratio
+    let (nx, ny) = match width >= height {
+        true => (COMPONENTS, (COMPONENTS * height / width.max(1)).clamp(1, COMPONENTS)),
+        false => ((COMPONENTS * width / height.max(1)).clamp(1, COMPONENTS), COMPONENTS),
+    };
+
+    let pixels: Vec<[f32; 3]> = image.pixels().map(|p| p.0.map(srgb_to_linear)).collect();
+
+    let mut factors = Vec::with_capacity((nx * ny) as usize);
+
+    for j in 0..ny {
+        for i in 0..nx {
+            let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+
+            let mut sum = [0.0f32; 3];
+
+            for y in 0..height {
+                let basis_y = (std::f32::consts::PI * j as f32 * y as f32 / height as f32).cos();
+
+                for x in 0..width {
+                    let basis = basis_y * (std::f32::consts::PI * i as f32 * x as f32 / width as f32).cos();
+                    let pixel = pixels[(y * width + x) as usize];
+
+                    for c in 0..3 {
+                        sum[c] += basis * pixel[c];
+                    }
+                }
+            }
+
+            let scale = normalisation / (width * height) as f32;
+
+            factors.push(sum.map(|v| v * scale));
+        }
+    }
+
+    let (dc, ac) = factors.split_first().expect("at least one component");
+
+    let mut out = String::with_capacity(6 + ac.len() * 2);
+
+    encode83(&mut out, (nx - 1) + (ny - 1) * 9, 1);
+
+    let max_ac = match ac.iter().flatten().map(|v| v.abs()).reduce(f32::max) {
+        Some(actual) => {
+            let quantized = (actual * 166.0 - 0.5).floor().clamp(0.0, 82.0);
+            encode83(&mut out, quantized as u32, 1);
+            (quantized + 1.0) / 166.0
+        }
+        None => {
+            encode83(&mut out, 0, 1);
+            1.0
+        }
+    };
+
+    let [r, g, b] = dc.map(|v| linear_to_srgb(v) as u32);
+    encode83(&mut out, (r << 16) | (g << 8) | b, 4);
+
+    for factor in ac {
+        let [r, g, b] = factor.map(|v| (sign_pow(v / max_ac, 0.5) * 9.0 + 9.5).floor().clamp(0.0, 18.0) as u32);
+
+        encode83(&mut out, r * 19 * 19 + g * 19 + b, 2);
+    }
+
+    out
+}
+
+const BASE83: &[u8; 83] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+/// Appends `value` as `length` base-83 digits, most significant first
+fn encode83(out: &mut String, value: u32, length: u32) {
+    for i in (0..length).rev() {
+        out.push(BASE83[(value / 83u32.pow(i) % 83) as usize] as char);
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let v = value as f32 / 255.0;
+
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let v = value.clamp(0.0, 1.0);
+
+    let v = if v <= 0.0031308 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 };
+
+    (v * 255.0 + 0.5) as u8
+}
+
+fn sign_pow(value: f32, exp: f32) -> f32 {
+    value.abs().powf(exp).copysign(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageFormat, Rgb, RgbImage};
+
+    use super::*;
+
+    fn encode(image: &RgbImage, format: ImageFormat) -> Vec<u8> {
+        let mut out = std::io::Cursor::new(Vec::new());
+        image.write_to(&mut out, format).unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn test_probe_media() {
+        let image = RgbImage::from_fn(64, 48, |x, _| Rgb([(x * 4) as u8, 0, 255]));
+
+        for (format, mime) in [
+            (ImageFormat::Png, "image/png"),
+            (ImageFormat::Jpeg, "image/jpeg"),
+            (ImageFormat::Gif, "image/gif"),
+        ] {
+            let data = encode(&image, format);
+
+            let mut info = MediaInfo::probe(&data);
+
+            assert_eq!(info.mime.as_deref(), Some(mime));
+            assert_eq!((info.width, info.height), (Some(64), Some(48)));
+
+            info.generate_preview(&data);
+
+            let preview = info.preview.unwrap();
+
+            // 4x3 components
+            assert!(preview.starts_with('L'), "{preview}");
+            assert_eq!(preview.len(), 6 + 11 * 2);
+        }
+
+        assert_eq!(MediaInfo::probe(b"plain text"), MediaInfo::default());
+    }
+
+    fn decode83(digits: &str) -> u32 {
+        digits.bytes().fold(0, |value, c| value * 83 + BASE83.iter().position(|&d| d == c).unwrap() as u32)
+    }
+
+    #[test]
+    fn test_blurhash_solid_color() {
+        // same as the reference encoder with 4x3 components
+        let image = DynamicImage::ImageRgb8(RgbImage::new(64, 48));
+        assert_eq!(blurhash(&image), "L00000fQfQfQfQfQfQfQfQfQfQfQ");
+
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(16, 16, Rgb([255, 0, 0])));
+
+        let hash = blurhash(&image);
+
+        // square, so 4x4 components, with the DC being the color itself
+        assert_eq!(decode83(&hash[..1]), 3 + 3 * 9);
+        assert_eq!(decode83(&hash[2..6]), 0xFF0000);
+        assert_eq!(hash.len(), 6 + 15 * 2);
+    }
+}
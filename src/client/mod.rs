@@ -2,22 +2,55 @@ use std::sync::Arc;
 
 use arc_swap::{ArcSwap, ArcSwapOption};
 use headers::HeaderValue;
+use reqwest::Url;
 
 use crate::{
-    driver::{generic_client, Driver, DriverError, Encoding},
-    models::AuthToken,
+    api::commands::config::GetServerConfig,
+    driver::{
+        generic_client, normalize_base_url, Driver, DriverError, Encoding, Middleware, MiddlewareChain, RetryPolicy, Transport,
+    },
+    models::{AuthToken, ServerConfig},
 };
 
+#[cfg(feature = "ratelimit")]
+use crate::driver::RateLimiter;
+
 mod error;
 pub use error::ClientError;
 
+mod asset;
+pub use asset::CdnUrls;
+
+mod camo;
+pub use camo::{EmbedMediaKind, EmbedMediaUrl};
+
+mod chunk;
+pub use chunk::ChunkConfig;
+
 mod file;
+pub use file::UploadState;
+
+mod limits;
+pub use limits::{UploadKind, UploadLimitError};
+
+mod upload;
+pub use upload::{UploadCancel, UploadEvent, UploadHandle};
+
+#[cfg(feature = "media")]
+pub mod media;
 
 struct ClientInner {
-    inner: reqwest::Client,
+    inner: Arc<dyn Transport>,
     auth: ArcSwapOption<(AuthToken, HeaderValue)>,
     uri: Arc<str>,
+    gateway_uri: ArcSwapOption<Url>,
     preferred_encoding: ArcSwap<Encoding>,
+    #[cfg(feature = "ratelimit")]
+    rate_limiter: ArcSwapOption<RateLimiter>,
+    retry: ArcSwap<RetryPolicy>,
+    middleware: ArcSwap<Vec<Arc<dyn Middleware>>>,
+    chunk_config: ArcSwap<ChunkConfig>,
+    server_config: ArcSwapOption<ServerConfig>,
 }
 
 #[derive(Clone)]
@@ -30,22 +63,59 @@ impl ClientInner {
             inner: self.inner.clone(),
             auth: self.auth.load_full(),
             uri: self.uri.clone(),
+            gateway_uri: self.gateway_uri.load_full(),
             encoding: **self.preferred_encoding.load(),
+            #[cfg(feature = "ratelimit")]
+            rate_limiter: self.rate_limiter.load_full(),
+            retry: **self.retry.load(),
+            middleware: self.middleware.load_full(),
         }
     }
 }
 
 impl Client {
+    /// Constructs a client for the server at the given base URL, which may include a sub-path
+    /// such as `https://example.com/lantern`.
+    ///
+    /// Returns [`DriverError::InvalidUrl`] if the URL is not a valid `http` or `https` URL.
     pub fn new(uri: &str) -> Result<Self, ClientError> {
-        Ok(Self::from_client(generic_client().build()?, uri))
+        Self::try_from_client(generic_client().build()?, uri)
     }
 
+    /// Constructs a client from an existing client. The URL is assumed to be a valid base URL without a trailing slash,
+    /// see [`try_from_client`](Client::try_from_client) to validate it.
     pub fn from_client(client: reqwest::Client, uri: &str) -> Self {
+        Self::with_transport(Arc::new(client), Arc::from(uri))
+    }
+
+    /// Same as [`from_client`](Client::from_client), but validates the URL as with [`new`](Client::new).
+    pub fn try_from_client(client: reqwest::Client, uri: &str) -> Result<Self, ClientError> {
+        Self::from_transport(Arc::new(client), uri)
+    }
+
+    /// Constructs a client that performs requests using the given [`Transport`],
+    /// such as the [`MockTransport`](crate::driver::mock::MockTransport) for testing.
+    ///
+    /// Returns [`DriverError::InvalidUrl`] if the URL is not a valid `http` or `https` URL.
+    pub fn from_transport(transport: Arc<dyn Transport>, uri: &str) -> Result<Self, ClientError> {
+        let uri = normalize_base_url(uri).map_err(DriverError::from)?;
+
+        Ok(Self::with_transport(transport, uri))
+    }
+
+    fn with_transport(transport: Arc<dyn Transport>, uri: Arc<str>) -> Self {
         Client(Arc::new(ClientInner {
-            inner: client,
+            inner: transport,
             auth: ArcSwapOption::empty(),
-            uri: Arc::from(uri),
+            uri,
+            gateway_uri: ArcSwapOption::empty(),
             preferred_encoding: ArcSwap::from_pointee(Encoding::JSON),
+            #[cfg(feature = "ratelimit")]
+            rate_limiter: ArcSwapOption::empty(),
+            retry: ArcSwap::from_pointee(RetryPolicy::DEFAULT),
+            middleware: ArcSwap::new(MiddlewareChain::default()),
+            chunk_config: ArcSwap::from_pointee(ChunkConfig::DEFAULT),
+            server_config: ArcSwapOption::empty(),
         }))
     }
 
@@ -68,10 +138,94 @@ impl Client {
         self.0.auth.load().as_ref().map(|auth| auth.0)
     }
 
+    /// Base URL of the server, without a trailing slash
+    pub fn base_url(&self) -> &str {
+        &self.0.uri
+    }
+
+    /// Overrides the gateway websocket URL, such as when the server advertises a different host,
+    /// or derives it from the base URL again if `None`. See [`Driver::gateway_url`].
+    pub fn set_gateway_url(&self, uri: Option<&str>) -> Result<(), ClientError> {
+        let mut driver = self.driver();
+        driver.set_gateway_url(uri)?;
+
+        self.0.gateway_uri.store(driver.gateway_uri);
+
+        Ok(())
+    }
+
     pub fn set_preferred_encoding(&self, encoding: Encoding) {
         self.0.preferred_encoding.store(Arc::new(encoding));
     }
 
+    /// Enables client-side rate-limiting of commands with the given [`RateLimiter`], or disables it if `None`.
+    ///
+    /// The rate-limiter is shared by all [Driver] instances created afterwards, and may
+    /// also be shared between multiple clients using the same account.
+    #[cfg(feature = "ratelimit")]
+    pub fn set_rate_limiter(&self, rate_limiter: Option<Arc<RateLimiter>>) {
+        self.0.rate_limiter.store(rate_limiter);
+    }
+
+    #[cfg(feature = "ratelimit")]
+    pub fn rate_limiter(&self) -> Option<Arc<RateLimiter>> {
+        self.0.rate_limiter.load_full()
+    }
+
+    /// Sets the [`RetryPolicy`] inherited by all [Driver] instances created afterwards.
+    ///
+    /// Defaults to [`RetryPolicy::DEFAULT`], which only retries idempotent requests.
+    pub fn set_retry_policy(&self, retry: RetryPolicy) {
+        self.0.retry.store(Arc::new(retry));
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        **self.0.retry.load()
+    }
+
+    /// Appends a [`Middleware`] to the end of the chain run for each request,
+    /// inherited by all [Driver] instances created afterwards.
+    pub fn add_middleware(&self, middleware: impl Middleware) {
+        let middleware: Arc<dyn Middleware> = Arc::new(middleware);
+
+        self.0.middleware.rcu(|chain| {
+            let mut chain = Vec::clone(chain);
+            chain.push(middleware.clone());
+            chain
+        });
+    }
+
+    /// Removes all [`Middleware`] from the chain.
+    pub fn clear_middleware(&self) {
+        self.0.middleware.store(MiddlewareChain::default());
+    }
+
+    /// Sets the [`ChunkConfig`] used for file uploads.
+    pub fn set_chunk_config(&self, config: ChunkConfig) {
+        self.0.chunk_config.store(Arc::new(config));
+    }
+
+    pub fn chunk_config(&self) -> ChunkConfig {
+        **self.0.chunk_config.load()
+    }
+
+    /// Returns the [`ServerConfig`], fetching it from the server the first time.
+    pub async fn server_config(&self) -> Result<Arc<ServerConfig>, ClientError> {
+        match self.0.server_config.load_full() {
+            Some(config) => Ok(config),
+            None => self.refresh_server_config().await,
+        }
+    }
+
+    /// Fetches the [`ServerConfig`] from the server, replacing any cached copy.
+    pub async fn refresh_server_config(&self) -> Result<Arc<ServerConfig>, ClientError> {
+        let config = Arc::new(self.driver().execute(GetServerConfig::new()).await?);
+
+        self.0.server_config.store(Some(config.clone()));
+
+        Ok(config)
+    }
+
     /// Constructs a [Driver] instance with the current configuration. Changes to the Client configuration
     /// will not be reflected in the created Driver, and a new one must be constructed.
     ///
@@ -0,0 +1,377 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{channel::mpsc, Stream};
+use tokio::io::{AsyncRead, AsyncSeek};
+
+use super::{Client, ClientError, UploadKind, UploadState};
+use crate::{api::commands::file::CreateFileBody, driver::DriverError};
+
+/// Progress of an upload, given by the [`UploadHandle`] stream
+#[derive(Debug, Clone)]
+pub enum UploadEvent {
+    /// The upload has started, or resumed, from the given state
+    Started {
+        state: UploadState,
+    },
+
+    /// A chunk was acknowledged by the server, and the upload may be resumed from `state`
+    ChunkAcknowledged {
+        state: UploadState,
+
+        /// Smoothed upload throughput
+        bytes_per_second: f64,
+
+        /// Estimated time until the upload completes, if known
+        eta: Option<Duration>,
+    },
+
+    /// Uploading the chunk at `offset` failed, and will be retried
+    Retried {
+        offset: u64,
+        attempts: u32,
+        error: Arc<DriverError>,
+    },
+
+    Completed {
+        state: UploadState,
+    },
+
+    /// The upload was cancelled with [`UploadHandle::cancel`], and may be resumed from `state`
+    Cancelled {
+        state: UploadState,
+    },
+
+    /// The upload failed, with the same error returned by the upload task.
+    ///
+    /// If the file was created, it may be resumed from `state`.
+    Failed {
+        state: Option<UploadState>,
+        error: Arc<ClientError>,
+    },
+}
+
+/// Cooperative cancellation for an upload, checked between chunks
+#[derive(Debug, Default, Clone)]
+pub struct UploadCancel(Arc<AtomicBool>);
+
+impl UploadCancel {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Stream of [`UploadEvent`]s for an upload task, which can also cancel it.
+///
+/// The stream ends once the upload task has completed, failed or been cancelled.
+pub struct UploadHandle {
+    events: mpsc::UnboundedReceiver<UploadEvent>,
+    cancel: UploadCancel,
+}
+
+impl UploadHandle {
+    fn new() -> (mpsc::UnboundedSender<UploadEvent>, UploadHandle) {
+        let (tx, events) = mpsc::unbounded();
+
+        (
+            tx,
+            UploadHandle {
+                events,
+                cancel: UploadCancel::default(),
+            },
+        )
+    }
+
+    /// Stops the upload after the current chunk is acknowledged, leaving it in a resumable state.
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Returns a handle that can cancel the upload independently of this stream
+    pub fn canceller(&self) -> UploadCancel {
+        self.cancel.clone()
+    }
+}
+
+impl Stream for UploadHandle {
+    type Item = UploadEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.events).poll_next(cx)
+    }
+}
+
+/// Exponentially-weighted moving average of upload throughput
+#[derive(Default)]
+pub(crate) struct Throughput {
+    rate: Option<f64>,
+}
+
+impl Throughput {
+    /// Weight given to the most recent chunk
+    const ALPHA: f64 = 0.3;
+
+    pub fn update(&mut self, bytes: u64, elapsed: Duration) {
+        let rate = bytes as f64 / elapsed.as_secs_f64().max(0.001);
+
+        self.rate = Some(match self.rate {
+            Some(prev) => prev + Self::ALPHA * (rate - prev),
+            None => rate,
+        });
+    }
+
+    pub fn bytes_per_second(&self) -> f64 {
+        self.rate.unwrap_or_default()
+    }
+
+    pub fn eta(&self, remaining: u64) -> Option<Duration> {
+        match self.rate {
+            Some(rate) if rate > 0.0 => Duration::try_from_secs_f64(remaining as f64 / rate).ok(),
+            _ => None,
+        }
+    }
+}
+
+impl Client {
+    /// Same as [`upload_stream_as`](Client::upload_stream_as), but returns the upload task along with
+    /// an [`UploadHandle`] to follow its progress or cancel it.
+    ///
+    /// The task must be awaited or spawned for the upload to progress. If cancelled,
+    /// it returns [`ClientError::UploadCancelled`] with the state to resume from.
+    /// Any other error is shared with [`UploadEvent::Failed`].
+    pub fn upload_stream_with_handle(
+        &self,
+        kind: UploadKind,
+        meta: CreateFileBody,
+        stream: impl AsyncRead,
+    ) -> (impl Future<Output = Result<UploadState, Arc<ClientError>>>, UploadHandle) {
+        let (tx, handle) = UploadHandle::new();
+        let cancel = handle.canceller();
+        let client = self.clone();
+
+        let task = async move {
+            let mut state = match client.begin_upload_as(kind, meta).await {
+                Ok(state) => state,
+                Err(e) => return Err(failed(&tx, None, e)),
+            };
+
+            client.run_upload(&mut state, std::pin::pin!(stream), &tx, &cancel).await.map(|_| state)
+        };
+
+        (task, handle)
+    }
+
+    /// Same as [`resume_upload`](Client::resume_upload), but returns the upload task along with
+    /// an [`UploadHandle`] to follow its progress or cancel it.
+    pub fn resume_upload_with_handle(
+        &self,
+        mut state: UploadState,
+        source: impl AsyncRead + AsyncSeek,
+    ) -> (impl Future<Output = Result<UploadState, Arc<ClientError>>>, UploadHandle) {
+        let (tx, handle) = UploadHandle::new();
+        let cancel = handle.canceller();
+        let client = self.clone();
+
+        let task = async move {
+            let mut source = std::pin::pin!(source);
+
+            if let Err(e) = client.sync_upload(&mut state, source.as_mut()).await {
+                return Err(failed(&tx, Some(state), e));
+            }
+
+            client.run_upload(&mut state, source, &tx, &cancel).await.map(|_| state)
+        };
+
+        (task, handle)
+    }
+
+    async fn run_upload<S: AsyncRead>(
+        &self,
+        state: &mut UploadState,
+        stream: Pin<&mut S>,
+        tx: &mpsc::UnboundedSender<UploadEvent>,
+        cancel: &UploadCancel,
+    ) -> Result<(), Arc<ClientError>> {
+        // the receiving end may have been dropped, but the upload should continue regardless
+        let mut events = |event| {
+            let _ = tx.unbounded_send(event);
+        };
+
+        events(UploadEvent::Started { state: *state });
+
+        match self.upload_chunks(state, stream, &mut events, Some(cancel)).await {
+            Ok(_) => {
+                events(UploadEvent::Completed { state: *state });
+                Ok(())
+            }
+            Err(ClientError::UploadCancelled(state)) => {
+                events(UploadEvent::Cancelled { state });
+                Err(Arc::new(ClientError::UploadCancelled(state)))
+            }
+            Err(e) => Err(failed(tx, Some(*state), e)),
+        }
+    }
+}
+
+/// Sends [`UploadEvent::Failed`], returning the shared error for the upload task to return as well
+fn failed(tx: &mpsc::UnboundedSender<UploadEvent>, state: Option<UploadState>, error: ClientError) -> Arc<ClientError> {
+    let error = Arc::new(error);
+    let _ = tx.unbounded_send(UploadEvent::Failed {
+        state,
+        error: error.clone(),
+    });
+    error
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use http::{HeaderName, HeaderValue, StatusCode};
+    use reqwest::Request;
+
+    use super::*;
+
+    use crate::{
+        api::{
+            commands::{config::GetServerConfig, file::CreateFile},
+            error::ApiErrorCode,
+        },
+        client::ChunkConfig,
+        driver::{
+            mock::{MockResponse, MockTransport},
+            CommandInfo, Next, TransportFuture,
+        },
+        models::{ServerConfig, ServerLimits, Snowflake},
+    };
+
+    #[tokio::test]
+    async fn test_upload_handle_cancel() {
+        let mock = Arc::new(MockTransport::default());
+
+        let client = Client::from_transport(mock.clone(), "https://lantern.invalid").unwrap();
+        client.set_auth(Some("a".repeat(28).parse().unwrap())).unwrap();
+        client.set_chunk_config(ChunkConfig::fixed(8));
+
+        mock.respond::<GetServerConfig>(MockResponse::json(&ServerConfig {
+            hcaptcha_sitekey: String::new(),
+            cdn: String::new(),
+            min_age: 0,
+            secure: true,
+            limits: ServerLimits {
+                max_upload_size: 1024,
+                max_avatar_size: 0,
+                max_banner_size: 0,
+                max_avatar_pixels: 0,
+                max_banner_pixels: 0,
+                avatar_width: 0,
+                banner_width: 0,
+                banner_height: 0,
+            },
+            camo: false,
+        }))
+        .respond::<CreateFile>(MockResponse::json(&"1".parse::<Snowflake>().unwrap()));
+
+        // cache the limits to check against
+        client.server_config().await.unwrap();
+
+        for offset in [8u64, 16, 24, 32] {
+            mock.respond_raw(
+                CommandInfo::PATCH_FILE.name,
+                MockResponse::new(StatusCode::NO_CONTENT)
+                    .with_header(HeaderName::from_static("upload-offset"), HeaderValue::from(offset)),
+            );
+        }
+
+        let data: Vec<u8> = (0..32).collect();
+
+        let meta = CreateFileBody {
+            filename: "test.bin".into(),
+            size: data.len() as i32,
+            mime: None,
+            width: None,
+            height: None,
+            preview: None,
+        };
+
+        let (task, handle) = client.upload_stream_with_handle(UploadKind::File, meta, &data[..]);
+
+        // cancel while the second chunk is being uploaded
+        let cancel = handle.canceller();
+        client.add_middleware(move |info: &CommandInfo, req: Request, next: Next| -> TransportFuture {
+            if req.headers().get("upload-offset").is_some_and(|offset| offset == "8") {
+                cancel.cancel();
+            }
+
+            next.run(info, req)
+        });
+
+        let state = match *task.await.unwrap_err() {
+            ClientError::UploadCancelled(state) => state,
+            ref e => panic!("expected cancellation, got {e:?}"),
+        };
+
+        assert_eq!(state.offset, 16);
+        assert_eq!(state.crc32, crc32fast::hash(&data[..16]));
+
+        let events: Vec<_> = handle.collect().await;
+
+        assert_eq!(events.len(), 4);
+        assert!(matches!(events[0], UploadEvent::Started { state } if state.offset == 0));
+        assert!(matches!(events[1], UploadEvent::ChunkAcknowledged { state, .. } if state.offset == 8));
+        assert!(matches!(events[2], UploadEvent::ChunkAcknowledged { state, eta: Some(_), .. } if state.offset == 16));
+        assert!(matches!(events[3], UploadEvent::Cancelled { state: s } if s == state));
+    }
+
+    #[tokio::test]
+    async fn test_upload_handle_failed() {
+        let mock = Arc::new(MockTransport::default());
+
+        let client = Client::from_transport(mock.clone(), "https://lantern.invalid").unwrap();
+        client.set_auth(Some("a".repeat(28).parse().unwrap())).unwrap();
+
+        let meta = CreateFileBody {
+            filename: "test.bin".into(),
+            size: 4,
+            mime: None,
+            width: None,
+            height: None,
+            preview: None,
+        };
+
+        // no mock response for CreateFile, so the file is never created
+        let (task, handle) = client.upload_stream_with_handle(UploadKind::File, meta, &[0u8; 4][..]);
+
+        let err = task.await.unwrap_err();
+        assert!(matches!(*err, ClientError::ApiError(ref e) if e.code == ApiErrorCode::Unimplemented));
+
+        let events: Vec<_> = handle.collect().await;
+
+        assert_eq!(events.len(), 1);
+        assert!(matches!(events[0], UploadEvent::Failed { state: None, ref error } if Arc::ptr_eq(error, &err)));
+    }
+
+    #[test]
+    fn test_throughput() {
+        let mut throughput = Throughput::default();
+        assert_eq!(throughput.eta(100), None);
+
+        throughput.update(1000, Duration::from_secs(1));
+        assert_eq!(throughput.bytes_per_second(), 1000.0);
+        assert_eq!(throughput.eta(5000), Some(Duration::from_secs(5)));
+
+        throughput.update(2000, Duration::from_secs(1));
+        assert!((throughput.bytes_per_second() - 1300.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,147 @@
+use bytes::Bytes;
+use headers::{ContentLength, ContentRange, HeaderMapExt};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use reqwest::{Request, Url};
+
+use super::{DownloadBody, Driver, DriverError};
+
+/// Response body of a download started with [`Driver::download`], possibly resumed from an offset.
+///
+/// The body length is verified against `Content-Length` once fully read.
+pub struct Download {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+
+    offset: u64,
+    total: Option<u64>,
+    content_length: Option<u64>,
+    received: u64,
+
+    /// Bytes to discard from the start of the body, if the server ignored the range request
+    skip: u64,
+
+    body: DownloadBody,
+}
+
+impl Download {
+    /// Offset within the full resource of the first byte returned by [`chunk`](Download::chunk)
+    #[inline]
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Total length of the resource, if known
+    #[inline]
+    pub fn total_len(&self) -> Option<u64> {
+        self.total
+    }
+
+    /// Number of bytes remaining from [`offset`](Download::offset), if known
+    pub fn remaining(&self) -> Option<u64> {
+        self.content_length.map(|len| len.saturating_sub(self.received + self.skip))
+    }
+
+    /// Returns the next chunk of the body, or `None` once it has been fully read.
+    ///
+    /// Returns [`DriverError::ContentLengthMismatch`] if the body did not match the expected length.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, DriverError> {
+        loop {
+            let mut chunk = match self.body.chunk().await? {
+                Some(chunk) => chunk,
+                None => match self.content_length {
+                    Some(expected) if expected != self.received => {
+                        return Err(DriverError::ContentLengthMismatch {
+                            expected,
+                            received: self.received,
+                        });
+                    }
+                    _ => return Ok(None),
+                },
+            };
+
+            self.received += chunk.len() as u64;
+
+            if let Some(expected) = self.content_length {
+                if self.received > expected {
+                    return Err(DriverError::ContentLengthMismatch {
+                        expected,
+                        received: self.received,
+                    });
+                }
+            }
+
+            if self.skip > 0 {
+                let skipped = self.skip.min(chunk.len() as u64);
+                self.skip -= skipped;
+                chunk = chunk.slice(skipped as usize..);
+            }
+
+            if !chunk.is_empty() {
+                return Ok(Some(chunk));
+            }
+        }
+    }
+}
+
+impl Driver {
+    /// Starts downloading the given URL, resuming from `offset` with a range request if non-zero.
+    ///
+    /// Downloads are not [`Command`](crate::api::Command)s, so skip rate-limiting, retries and middleware,
+    /// and are sent without authorization.
+    pub async fn download(&self, url: Url, offset: u64) -> Result<Download, DriverError> {
+        let mut req = Request::new(Method::GET, url);
+
+        if offset > 0 {
+            req.headers_mut().insert(
+                HeaderName::from_static("range"),
+                HeaderValue::try_from(format!("bytes={offset}-"))?,
+            );
+        }
+
+        let (parts, body) = self.inner.download(req).await?.into_parts();
+
+        let content_length = parts.headers.typed_get::<ContentLength>().map(|len| len.0);
+        let content_range = parts.headers.typed_get::<ContentRange>();
+
+        let mut download = Download {
+            status: parts.status,
+            headers: parts.headers,
+            offset,
+            total: content_length,
+            content_length,
+            received: 0,
+            skip: 0,
+            body,
+        };
+
+        match parts.status {
+            StatusCode::PARTIAL_CONTENT => {
+                let range = content_range.as_ref().and_then(ContentRange::bytes_range);
+
+                if !matches!(range, Some((start, _)) if start == offset) {
+                    return Err(DriverError::InvalidContentRange(offset));
+                }
+
+                download.total = content_range.as_ref().and_then(ContentRange::bytes_len);
+            }
+            // the resource was already fully downloaded
+            StatusCode::RANGE_NOT_SATISFIABLE
+                if offset > 0 && content_range.as_ref().and_then(ContentRange::bytes_len) == Some(offset) =>
+            {
+                download.total = Some(offset);
+                download.content_length = Some(0);
+                download.body = DownloadBody::buffered(Bytes::new());
+            }
+            status if status.is_success() => {
+                download.skip = offset;
+
+                if content_length.is_some_and(|len| len < offset) {
+                    return Err(DriverError::InvalidContentRange(offset));
+                }
+            }
+            status => return Err(DriverError::GenericDriverError(status)),
+        }
+
+        Ok(download)
+    }
+}
@@ -0,0 +1,144 @@
+//! Validation of server base URLs, and derivation of the gateway URL from them.
+//!
+//! Base URLs may include a sub-path for deployments behind a reverse proxy,
+//! such as `https://example.com/lantern/`, which is normalized to `https://example.com/lantern`
+//! so that API routes can be appended directly.
+
+use std::sync::Arc;
+
+use reqwest::Url;
+
+#[derive(Debug, thiserror::Error)]
+pub enum UrlError {
+    #[error("Url Parse Error: {0}")]
+    ParseError(#[from] url::ParseError),
+
+    #[error("Unsupported URL Scheme \"{scheme}\", expected {expected}")]
+    UnsupportedScheme { scheme: String, expected: &'static str },
+
+    #[error("URL must not contain a query or fragment")]
+    UnexpectedQuery,
+}
+
+/// Parses and validates a server base URL, returning it without a trailing slash.
+pub(crate) fn normalize_base_url(uri: &str) -> Result<Arc<str>, UrlError> {
+    let mut url = Url::parse(uri.trim())?;
+
+    // the scheme is lowercased by the parser
+    match url.scheme() {
+        "http" | "https" => {}
+        scheme => {
+            return Err(UrlError::UnsupportedScheme {
+                scheme: scheme.to_owned(),
+                expected: "http or https",
+            })
+        }
+    }
+
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(UrlError::UnexpectedQuery);
+    }
+
+    let path = url.path().trim_end_matches('/').to_owned();
+    url.set_path(&path);
+
+    // an empty path is always serialized as "/"
+    Ok(Arc::from(url.as_str().trim_end_matches('/')))
+}
+
+/// Derives the websocket gateway URL from a base URL, e.g. `https://example.com/lantern`
+/// becomes `wss://example.com/lantern/api/v1/gateway`
+pub(crate) fn gateway_url(base: &str) -> Result<Url, UrlError> {
+    let mut url = Url::parse(base)?;
+
+    let scheme = match url.scheme() {
+        "http" => "ws",
+        "https" => "wss",
+        scheme => {
+            return Err(UrlError::UnsupportedScheme {
+                scheme: scheme.to_owned(),
+                expected: "http or https",
+            })
+        }
+    };
+
+    // switching between special schemes cannot fail
+    let _ = url.set_scheme(scheme);
+
+    if let Ok(mut segments) = url.path_segments_mut() {
+        segments.pop_if_empty().extend(["api", "v1", "gateway"]);
+    }
+
+    Ok(url)
+}
+
+/// Parses and validates an explicit gateway URL, accepting `ws`/`wss` or their `http`/`https` equivalents.
+///
+/// Query parameters are added when connecting, so the URL must not have any.
+pub(crate) fn parse_gateway_url(uri: &str) -> Result<Url, UrlError> {
+    let mut url = Url::parse(uri.trim())?;
+
+    let scheme = match url.scheme() {
+        "ws" | "http" => "ws",
+        "wss" | "https" => "wss",
+        scheme => {
+            return Err(UrlError::UnsupportedScheme {
+                scheme: scheme.to_owned(),
+                expected: "ws, wss, http or https",
+            })
+        }
+    };
+
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(UrlError::UnexpectedQuery);
+    }
+
+    let _ = url.set_scheme(scheme);
+
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base_urls() {
+        assert_eq!(
+            &*normalize_base_url("https://lantern.invalid").unwrap(),
+            "https://lantern.invalid"
+        );
+        assert_eq!(
+            &*normalize_base_url("HTTPS://Lantern.invalid/").unwrap(),
+            "https://lantern.invalid"
+        );
+        assert_eq!(
+            &*normalize_base_url("http://lantern.invalid:8080/chat//").unwrap(),
+            "http://lantern.invalid:8080/chat"
+        );
+
+        assert!(matches!(
+            normalize_base_url("ftp://lantern.invalid"),
+            Err(UrlError::UnsupportedScheme { .. })
+        ));
+        assert!(matches!(
+            normalize_base_url("https://lantern.invalid/?a=b"),
+            Err(UrlError::UnexpectedQuery)
+        ));
+        assert!(matches!(normalize_base_url("lantern.invalid"), Err(UrlError::ParseError(_))));
+
+        assert_eq!(
+            gateway_url("https://lantern.invalid").unwrap().as_str(),
+            "wss://lantern.invalid/api/v1/gateway"
+        );
+        assert_eq!(
+            gateway_url("http://lantern.invalid:8080/chat").unwrap().as_str(),
+            "ws://lantern.invalid:8080/chat/api/v1/gateway"
+        );
+
+        assert_eq!(
+            parse_gateway_url("HTTPS://gateway.lantern.invalid/gateway").unwrap().as_str(),
+            "wss://gateway.lantern.invalid/gateway"
+        );
+    }
+}
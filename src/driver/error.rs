@@ -11,6 +11,9 @@ pub enum DriverError {
     #[error("Url Parse Error: {0}")]
     UrlParseError(#[from] url::ParseError),
 
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(#[from] super::UrlError),
+
     #[error("Url Encoding Error: {0}")]
     UrlEncodingError(#[from] serde_urlencoded::ser::Error),
 
@@ -33,12 +36,22 @@ pub enum DriverError {
     #[error("Missing Authorization")]
     MissingAuthorization,
 
+    #[cfg(feature = "ratelimit")]
+    #[error("Rate-limited, retry after {0:?}")]
+    RateLimited(std::time::Duration),
+
     #[error("Invalid Header Value: {0}")]
     InvalidHeaderValue(#[from] http::header::InvalidHeaderValue),
 
     #[error("Parse Int Error: {0}")]
     ParseIntError(#[from] std::num::ParseIntError),
 
+    #[error("Download length mismatch, expected {expected} bytes but received {received}")]
+    ContentLengthMismatch { expected: u64, received: u64 },
+
+    #[error("Invalid Content-Range for download resumed at offset {0}")]
+    InvalidContentRange(u64),
+
     #[error("Header Parse Error: {0}")]
     HeaderParseError(#[from] http::header::ToStrError),
 }
@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use reqwest::Request;
+
+use super::{CommandInfo, Transport, TransportFuture};
+
+/// Interceptor for requests performed by the [`Driver`](super::Driver).
+///
+/// Middleware is run in the order it was added, each one receiving the fully-built request
+/// along with the [`Next`] step of the chain. It may modify the request before passing it on,
+/// inspect or transform the response or error afterwards, or short-circuit the chain entirely
+/// by returning a response without calling [`Next::run`].
+///
+/// Middleware is run for every attempt of a command, after rate-limiting and before retries.
+///
+/// ```ignore
+/// client.add_middleware(|info: &CommandInfo, mut req: Request, next: Next| -> TransportFuture {
+///     req.headers_mut().insert("x-request-id", HeaderValue::from(fastrand::u64(..)));
+///
+///     let name = info.name;
+///     let res = next.run(info, req);
+///
+///     Box::pin(async move {
+///         let res = res.await?;
+///         println!("{name}: {}", res.status());
+///         Ok(res)
+///     })
+/// });
+/// ```
+pub trait Middleware: Send + Sync + 'static {
+    fn handle(&self, info: &CommandInfo, req: Request, next: Next) -> TransportFuture;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(&CommandInfo, Request, Next) -> TransportFuture + Send + Sync + 'static,
+{
+    fn handle(&self, info: &CommandInfo, req: Request, next: Next) -> TransportFuture {
+        self(info, req, next)
+    }
+}
+
+/// Ordered list of middleware shared between [`Driver`](super::Driver) instances
+pub(crate) type MiddlewareChain = Arc<Vec<Arc<dyn Middleware>>>;
+
+/// The remainder of the middleware chain, ending with the [`Transport`]
+pub struct Next {
+    pub(crate) transport: Arc<dyn Transport>,
+    pub(crate) middleware: MiddlewareChain,
+    pub(crate) index: usize,
+}
+
+impl Next {
+    /// Pass the request on to the next middleware, or the transport if this is the end of the chain.
+    pub fn run(self, info: &CommandInfo, req: Request) -> TransportFuture {
+        match self.middleware.get(self.index).cloned() {
+            Some(middleware) => middleware.handle(
+                info,
+                req,
+                Next {
+                    index: self.index + 1,
+                    ..self
+                },
+            ),
+            None => self.transport.execute(info, req),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use http::{HeaderValue, Response, StatusCode};
+
+    use super::*;
+
+    use crate::{
+        api::commands::file::{FilesystemStatus, GetFilesystemStatus},
+        driver::{
+            mock::{MockResponse, MockTransport},
+            Driver,
+        },
+    };
+
+    #[tokio::test]
+    async fn test_middleware_chain() {
+        let mock = Arc::new(MockTransport::default());
+
+        mock.respond::<GetFilesystemStatus>(MockResponse::json(&FilesystemStatus {
+            quota_used: 1,
+            quota_total: 2,
+        }));
+
+        let mut driver = Driver::new_with_transport(Arc::from("https://lantern.invalid"), mock.clone());
+        driver.set_token(Some("a".repeat(28).parse().unwrap())).unwrap();
+
+        driver.add_middleware(|info: &CommandInfo, mut req: Request, next: Next| -> TransportFuture {
+            req.headers_mut().insert("x-test", HeaderValue::from_static("1"));
+            next.run(info, req)
+        });
+
+        let res = driver.execute(GetFilesystemStatus::new()).await.unwrap();
+        assert_eq!(res.quota_used, 1);
+
+        let requests = mock.take_requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].headers.get("x-test").unwrap(), "1");
+
+        // short-circuits if the previous middleware marked the request, so never reaches the mock
+        driver.add_middleware(|info: &CommandInfo, req: Request, next: Next| -> TransportFuture {
+            if req.headers().contains_key("x-test") {
+                let body = Bytes::from_static(br#"{"quota_used":3,"quota_total":4}"#);
+                return Box::pin(std::future::ready(Ok(Response::new(body))));
+            }
+
+            next.run(info, req)
+        });
+
+        let res = driver.execute(GetFilesystemStatus::new()).await.unwrap();
+        assert_eq!(res.quota_used, 3);
+
+        driver.clear_middleware();
+        driver.add_middleware(|_: &CommandInfo, _: Request, _: Next| -> TransportFuture {
+            let mut res = Response::new(Bytes::from_static(br#"{"code":40404,"message":"Not Found"}"#));
+            *res.status_mut() = StatusCode::NOT_FOUND;
+            Box::pin(std::future::ready(Ok(res)))
+        });
+
+        assert!(driver.execute_opt(GetFilesystemStatus::new()).await.unwrap().is_none());
+        assert!(mock.requests().is_empty());
+    }
+}
@@ -0,0 +1,334 @@
+//! In-memory [`Transport`] for testing code built on the [`Driver`](super::Driver) or [`Client`](crate::client::Client)
+//! without a real server.
+//!
+//! ```ignore
+//! let mock = Arc::new(MockTransport::default());
+//!
+//! mock.respond::<GetFilesystemStatus>(MockResponse::json(&FilesystemStatus { quota_used: 0, quota_total: 1024 }));
+//!
+//! let driver = Driver::new_with_transport(Arc::from("https://lantern.invalid"), mock.clone());
+//!
+//! driver.execute(GetFilesystemStatus::new()).await?;
+//!
+//! assert_eq!(mock.requests()[0].path(), "/file");
+//! ```
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+use bytes::Bytes;
+use headers::{ContentType, HeaderMapExt};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, Response, StatusCode};
+use reqwest::{Request, Url};
+
+use crate::api::{
+    error::{ApiError, ApiErrorCode},
+    Command,
+};
+
+use super::{deserialize_ct, CommandInfo, DriverError, Transport, TransportFuture};
+
+/// Canned response returned by the [`MockTransport`]
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl MockResponse {
+    /// Empty response with the given status code
+    pub fn new(status: StatusCode) -> Self {
+        MockResponse {
+            status,
+            headers: HeaderMap::new(),
+            body: Bytes::new(),
+        }
+    }
+
+    /// `200 OK` response with the value encoded as JSON
+    pub fn json<T: serde::Serialize>(value: &T) -> Self {
+        let mut res = MockResponse::new(StatusCode::OK);
+
+        res.body = serde_json::to_vec(value).expect("unable to serialize mock response").into();
+        res.headers.typed_insert(ContentType::json());
+        res
+    }
+
+    /// Error response with the given status code and [`ApiError`] body
+    pub fn error(status: StatusCode, code: ApiErrorCode, message: &'static str) -> Self {
+        MockResponse {
+            status,
+            ..MockResponse::json(&ApiError {
+                code,
+                message: message.into(),
+            })
+        }
+    }
+
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = body.into();
+        self
+    }
+}
+
+/// Request recorded by the [`MockTransport`]
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    /// See [`CommandInfo::name`]
+    pub command: &'static str,
+    pub method: Method,
+    pub url: Url,
+    pub headers: HeaderMap,
+    pub body: Option<Bytes>,
+}
+
+impl MockRequest {
+    /// Returns true if this request was made by the given command type
+    pub fn is<CMD: Command>(&self) -> bool {
+        self.command == std::any::type_name::<CMD>()
+    }
+
+    /// Route of the request, as formatted by [`Command::format_path`], without the API prefix.
+    pub fn path(&self) -> String {
+        let path = self.url.path();
+        let path = path.strip_prefix("/api/v1").unwrap_or(path);
+
+        format!("/{}", path.trim_start_matches('/'))
+    }
+
+    /// Decodes the request body according to its `Content-Type`
+    pub fn body<T: serde::de::DeserializeOwned>(&self) -> Result<T, DriverError> {
+        deserialize_ct(
+            self.body.as_deref().unwrap_or_default(),
+            self.headers.typed_get::<ContentType>(),
+        )
+    }
+
+    /// Decodes the query parameters, which is where the body of `GET`-like commands is placed
+    pub fn query<T: serde::de::DeserializeOwned>(&self) -> Result<T, serde_urlencoded::de::Error> {
+        serde_urlencoded::from_str(self.url.query().unwrap_or_default())
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    responses: HashMap<&'static str, VecDeque<MockResponse>>,
+    requests: Vec<MockRequest>,
+}
+
+/// In-memory [`Transport`] that replies with canned responses per command type and records all requests.
+///
+/// Responses for a command are returned in the order they were registered, with the last one
+/// repeating indefinitely. Commands without a registered response receive `501 Not Implemented`.
+#[derive(Default)]
+pub struct MockTransport {
+    state: Mutex<MockState>,
+}
+
+impl MockTransport {
+    fn state(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queue a response for the given command type
+    pub fn respond<CMD: Command>(&self, response: MockResponse) -> &Self {
+        self.respond_raw(std::any::type_name::<CMD>(), response)
+    }
+
+    /// Queue a response for the given [`CommandInfo::name`], such as [`CommandInfo::PATCH_FILE`]
+    pub fn respond_raw(&self, command: &'static str, response: MockResponse) -> &Self {
+        self.state().responses.entry(command).or_default().push_back(response);
+        self
+    }
+
+    /// All requests sent so far, in order
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.state().requests.clone()
+    }
+
+    /// Take all requests sent so far, clearing the log
+    pub fn take_requests(&self) -> Vec<MockRequest> {
+        std::mem::take(&mut self.state().requests)
+    }
+}
+
+impl Transport for MockTransport {
+    fn execute(&self, cmd: &CommandInfo, req: Request) -> TransportFuture {
+        let mut state = self.state();
+
+        let response = match state.responses.get_mut(cmd.name) {
+            Some(queue) if queue.len() > 1 => queue.pop_front(),
+            Some(queue) => queue.front().cloned(),
+            None => None,
+        };
+
+        let response = response.unwrap_or_else(|| {
+            MockResponse::error(
+                StatusCode::NOT_IMPLEMENTED,
+                ApiErrorCode::Unimplemented,
+                "No mock response registered",
+            )
+        });
+
+        state.requests.push(MockRequest {
+            command: cmd.name,
+            method: req.method().clone(),
+            url: req.url().clone(),
+            headers: req.headers().clone(),
+            body: req.body().and_then(|body| body.as_bytes()).map(Bytes::copy_from_slice),
+        });
+
+        let mut res = Response::new(response.body);
+
+        *res.status_mut() = response.status;
+        *res.headers_mut() = response.headers;
+
+        Box::pin(std::future::ready(Ok(res)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    use crate::{
+        api::commands::{
+            file::{FilesystemStatus, GetFilesystemStatus},
+            room::{CreateMessage, CreateMessageBody},
+        },
+        driver::Driver,
+        models::Snowflake,
+    };
+
+    fn driver(mock: &Arc<MockTransport>) -> Driver {
+        let mut driver = Driver::new_with_transport(Arc::from("https://lantern.invalid"), mock.clone());
+        driver.set_token(Some("a".repeat(28).parse().unwrap())).unwrap();
+        driver
+    }
+
+    #[tokio::test]
+    async fn test_mock_responses() {
+        let mock = Arc::new(MockTransport::default());
+
+        let status = FilesystemStatus {
+            quota_used: 10,
+            quota_total: 100,
+        };
+
+        mock.respond::<GetFilesystemStatus>(MockResponse::json(&status));
+
+        let res = driver(&mock).execute(GetFilesystemStatus::new()).await.unwrap();
+        assert_eq!(res, status);
+
+        // last response repeats
+        let res = driver(&mock).execute(GetFilesystemStatus::new()).await.unwrap();
+        assert_eq!(res, status);
+
+        let requests = mock.take_requests();
+        assert_eq!(requests.len(), 2);
+
+        let req = &requests[0];
+        assert!(req.is::<GetFilesystemStatus>());
+        assert_eq!(req.method, Method::OPTIONS);
+        assert_eq!(req.path(), "/file");
+        assert!(req.headers.contains_key("authorization"));
+        assert!(mock.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_execute_with_meta() {
+        let mock = Arc::new(MockTransport::default());
+
+        let status = FilesystemStatus {
+            quota_used: 10,
+            quota_total: 100,
+        };
+
+        mock.respond::<GetFilesystemStatus>(MockResponse::new(StatusCode::SERVICE_UNAVAILABLE));
+        mock.respond::<GetFilesystemStatus>(
+            MockResponse::json(&status).with_header(HeaderName::from_static("etag"), HeaderValue::from_static("\"abc\"")),
+        );
+
+        let mut driver = driver(&mock);
+        driver.set_retry_policy(crate::driver::RetryPolicy {
+            base_delay: std::time::Duration::ZERO,
+            ..Default::default()
+        });
+
+        let res = driver.execute_with_meta(GetFilesystemStatus::new()).await.unwrap();
+
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.header("etag"), Some("\"abc\""));
+        assert_eq!(res.attempts, 2);
+        assert_eq!(res.result, status);
+    }
+
+    #[tokio::test]
+    async fn test_retries_exhausted() {
+        let mock = Arc::new(MockTransport::default());
+
+        mock.respond::<GetFilesystemStatus>(MockResponse::error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            ApiErrorCode::InternalError,
+            "Internal Error",
+        ));
+
+        let mut driver = driver(&mock);
+        driver.set_retry_policy(crate::driver::RetryPolicy {
+            base_delay: std::time::Duration::ZERO,
+            ..Default::default()
+        });
+
+        // the final error is returned as-is
+        match driver.execute(GetFilesystemStatus::new()).await {
+            Err(DriverError::ApiError(err)) => assert_eq!(err.code, ApiErrorCode::InternalError),
+            res => panic!("expected api error, got {res:?}"),
+        }
+
+        assert_eq!(mock.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_mock_request_body() {
+        let mock = Arc::new(MockTransport::default());
+
+        mock.respond::<CreateMessage>(MockResponse::error(
+            StatusCode::FORBIDDEN,
+            ApiErrorCode::Unauthorized,
+            "Unauthorized",
+        ));
+
+        let room_id: Snowflake = "1".parse().unwrap();
+        let cmd = CreateMessage::new(
+            room_id,
+            "Hello".into(),
+            None,
+            Default::default(),
+            Default::default(),
+            false,
+            false,
+        );
+
+        match driver(&mock).execute(cmd).await {
+            Err(DriverError::ApiError(err)) => assert_eq!(err.code, ApiErrorCode::Unauthorized),
+            res => panic!("expected api error, got {res:?}"),
+        }
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path(), "/room/1/messages");
+
+        let body: CreateMessageBody = requests[0].body().unwrap();
+        assert_eq!(body.content, "Hello");
+    }
+}
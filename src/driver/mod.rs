@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use bytes::Bytes;
 use headers::{ContentType, HeaderMapExt, HeaderName, HeaderValue};
 use http::Method;
 use reqwest::{Request, Url};
@@ -7,8 +8,41 @@ use reqwest::{Request, Url};
 mod error;
 pub use error::DriverError;
 
+#[cfg(feature = "ratelimit")]
+mod ratelimit;
+#[cfg(feature = "ratelimit")]
+pub use ratelimit::{RateLimitMode, RateLimiter};
+
+#[cfg(feature = "retry")]
+mod retry;
+#[cfg(feature = "retry")]
+pub use retry::RetryPolicy;
+
+mod transport;
+pub use transport::{CommandInfo, DownloadBody, DownloadFuture, Transport, TransportFuture};
+
+mod download;
+pub use download::Download;
+
+mod endpoint;
+pub(crate) use endpoint::normalize_base_url;
+pub use endpoint::UrlError;
+
+mod response;
+pub use response::CommandResponse;
+
+mod middleware;
+pub(crate) use middleware::MiddlewareChain;
+pub use middleware::{Middleware, Next};
+
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+
 use crate::{
-    api::{Command, CommandFlags},
+    api::{
+        commands::file::{FileStatus, GetFileStatus},
+        Command, CommandFlags,
+    },
     models::{AuthToken, Snowflake},
 };
 
@@ -24,10 +58,16 @@ pub enum Encoding {
 
 #[derive(Clone)]
 pub struct Driver {
-    pub(crate) inner: reqwest::Client,
+    pub(crate) inner: Arc<dyn Transport>,
     pub(crate) encoding: Encoding,
     pub(crate) uri: Arc<str>,
+    pub(crate) gateway_uri: Option<Arc<Url>>,
     pub(crate) auth: Option<Arc<(AuthToken, HeaderValue)>>,
+    #[cfg(feature = "ratelimit")]
+    pub(crate) rate_limiter: Option<Arc<RateLimiter>>,
+    #[cfg(feature = "retry")]
+    pub(crate) retry: RetryPolicy,
+    pub(crate) middleware: MiddlewareChain,
 }
 
 pub(crate) fn generic_client() -> reqwest::ClientBuilder {
@@ -47,20 +87,67 @@ pub(crate) fn generic_client() -> reqwest::ClientBuilder {
 }
 
 impl Driver {
+    /// Constructs a driver for the server at the given base URL, which may include a sub-path.
+    ///
+    /// Returns [`DriverError::InvalidUrl`] if the URL is not a valid `http` or `https` URL.
     pub fn new(uri: &str) -> Result<Self, DriverError> {
-        Self::new_shared(Arc::from(uri))
+        Ok(Self::new_from_raw(normalize_base_url(uri)?, generic_client().build()?))
     }
 
+    /// Same as [`new`](Driver::new), but with a shared URL.
     pub fn new_shared(uri: Arc<str>) -> Result<Self, DriverError> {
-        Ok(Self::new_from_raw(uri, generic_client().build()?))
+        Self::new(&uri)
     }
 
+    /// Constructs a driver from an existing client. The URL is assumed to be a valid base URL without a trailing slash,
+    /// see [`new`](Driver::new).
     pub fn new_from_raw(uri: Arc<str>, client: reqwest::Client) -> Self {
+        Self::new_with_transport(uri, Arc::new(client))
+    }
+
+    /// Construct a driver that performs requests using the given [`Transport`],
+    /// such as the [`MockTransport`](mock::MockTransport) for testing.
+    ///
+    /// The URL is assumed to be a valid base URL without a trailing slash, see [`new`](Driver::new).
+    pub fn new_with_transport(uri: Arc<str>, transport: Arc<dyn Transport>) -> Self {
         Driver {
-            inner: client,
+            inner: transport,
             uri,
+            gateway_uri: None,
             encoding: Encoding::JSON,
             auth: None,
+            #[cfg(feature = "ratelimit")]
+            rate_limiter: None,
+            #[cfg(feature = "retry")]
+            retry: RetryPolicy::DEFAULT,
+            middleware: MiddlewareChain::default(),
+        }
+    }
+
+    /// Base URL of the server, without a trailing slash
+    #[inline]
+    pub fn base_url(&self) -> &str {
+        &self.uri
+    }
+
+    /// Overrides the gateway websocket URL, such as when the server advertises a different host,
+    /// or derives it from the base URL again if `None`.
+    pub fn set_gateway_url(&mut self, uri: Option<&str>) -> Result<(), DriverError> {
+        self.gateway_uri = match uri {
+            Some(uri) => Some(Arc::new(endpoint::parse_gateway_url(uri)?)),
+            None => None,
+        };
+
+        Ok(())
+    }
+
+    /// Websocket URL of the gateway, without any query parameters.
+    ///
+    /// Defaults to `/api/v1/gateway` relative to the base URL, with the scheme changed to `ws` or `wss`.
+    pub fn gateway_url(&self) -> Result<Url, UrlError> {
+        match self.gateway_uri {
+            Some(ref uri) => Ok(Url::clone(uri)),
+            None => endpoint::gateway_url(&self.uri),
         }
     }
 
@@ -73,6 +160,28 @@ impl Driver {
         Ok(())
     }
 
+    /// Sets the client-side [`RateLimiter`] used to pace commands, or disables rate-limiting if `None`.
+    #[cfg(feature = "ratelimit")]
+    pub fn set_rate_limiter(&mut self, rate_limiter: Option<Arc<RateLimiter>>) {
+        self.rate_limiter = rate_limiter;
+    }
+
+    /// Sets the [`RetryPolicy`] for failed commands.
+    #[cfg(feature = "retry")]
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    /// Appends a [`Middleware`] to the end of the chain.
+    pub fn add_middleware(&mut self, middleware: impl Middleware) {
+        Arc::make_mut(&mut self.middleware).push(Arc::new(middleware));
+    }
+
+    /// Removes all [`Middleware`] from the chain.
+    pub fn clear_middleware(&mut self) {
+        self.middleware = MiddlewareChain::default();
+    }
+
     fn add_auth_header(&self, req: &mut Request) -> Result<(), DriverError> {
         match self.auth {
             Some(ref auth) => {
@@ -97,11 +206,18 @@ impl Driver {
     ///
     /// If you would like an `Option` for not-found values, use [`execute_opt`](Driver::execute_opt) instead.
     pub async fn execute<CMD: Command>(&self, cmd: CMD) -> Result<CMD::Result, DriverError> {
+        self.execute_with_meta(cmd).await.map(CommandResponse::into_result)
+    }
+
+    /// Same as [`execute`](Driver::execute), but also returns the response status, headers and timing.
+    pub async fn execute_with_meta<CMD: Command>(&self, cmd: CMD) -> Result<CommandResponse<CMD::Result>, DriverError> {
         let mut path = format!("{}/api/v1/", self.uri);
+        let route_start = path.len();
 
         // likely inlined, simple
         cmd.format_path(&mut path)?;
 
+        let info = CommandInfo::of(&cmd);
         let mut req = Request::new(CMD::HTTP_METHOD, Url::parse(&path)?);
 
         // likely inlined, often no-ops
@@ -154,27 +270,104 @@ impl Driver {
             self.add_auth_header(&mut req)?;
         }
 
-        let response = self.inner.execute(req).await?;
+        let res = self.send(&info, &path[route_start..], req).await?;
 
-        let status = response.status();
-        let ct = response.headers().typed_get::<ContentType>();
-        let body = response.bytes().await?;
+        let result = if res.result.is_empty() || std::mem::size_of::<CMD::Result>() == 0 {
+            // if Result is a zero-size type, this is likely optimized away entirely.
+            // Otherwise, if the body is empty, try to deserialize an empty object
+            serde_json::from_slice(b"{}")?
+        } else {
+            deserialize_ct(&res.result, res.headers.typed_get::<ContentType>())?
+        };
 
-        if !status.is_success() {
-            return Err(match deserialize_ct(&body, ct) {
-                Ok(api_error) => DriverError::ApiError(api_error),
-                Err(_) => DriverError::GenericDriverError(status),
-            });
+        Ok(res.map(|_| result))
+    }
+
+    /// Sends the request, converting unsuccessful responses to errors.
+    #[cfg(not(feature = "retry"))]
+    async fn send(&self, info: &CommandInfo, route: &str, req: Request) -> Result<CommandResponse<Bytes>, DriverError> {
+        self.send_once(info, route, req).await.and_then(check_status)
+    }
+
+    /// Sends the request, retrying it as per the [`RetryPolicy`].
+    ///
+    /// Unsuccessful responses are converted to errors, and the number of attempts made is
+    /// recorded in [`CommandResponse::attempts`] on success.
+    #[cfg(feature = "retry")]
+    async fn send(&self, info: &CommandInfo, route: &str, mut req: Request) -> Result<CommandResponse<Bytes>, DriverError> {
+        let can_retry = self.retry.applies_to(&info.method);
+
+        let mut attempts = 1;
+
+        loop {
+            // keep a copy of the request around if it may be retried
+            let next = match can_retry && attempts < self.retry.max_attempts {
+                true => req.try_clone(),
+                false => None,
+            };
+
+            let res = self.send_once(info, route, req).await;
+
+            let retry_after = match res {
+                Ok(ref res) if retry::is_transient_status(res.status) => Some(retry::retry_after(&res.headers)),
+                Err(DriverError::ReqwestError(ref e)) if e.is_timeout() || e.is_connect() => Some(None),
+                _ => None,
+            };
+
+            let delay = match (next, retry_after) {
+                (Some(next), Some(retry_after)) => {
+                    req = next;
+                    self.retry.delay(attempts, retry_after)
+                }
+                _ => {
+                    return res.and_then(check_status).map(|res| CommandResponse { attempts, ..res });
+                }
+            };
+
+            tokio::time::sleep(delay).await;
+
+            attempts += 1;
         }
+    }
 
-        if body.is_empty() || std::mem::size_of::<CMD::Result>() == 0 {
-            // if Result is a zero-size type, this is likely optimized away entirely.
-            // Otherwise, if the body is empty, try to deserialize an empty object
-            return Ok(serde_json::from_slice(b"{}")?);
+    /// Sends a single attempt of the request, after waiting on the rate-limiter, if any.
+    #[allow(unused_variables)]
+    async fn send_once(&self, info: &CommandInfo, route: &str, req: Request) -> Result<CommandResponse<Bytes>, DriverError> {
+        #[cfg(feature = "ratelimit")]
+        if let Some(ref rate_limiter) = self.rate_limiter {
+            rate_limiter.acquire(info.name, route, info.rate_limit).await?;
         }
 
-        deserialize_ct(&body, ct)
+        let next = Next {
+            transport: self.inner.clone(),
+            middleware: self.middleware.clone(),
+            index: 0,
+        };
+
+        let start = std::time::Instant::now();
+
+        let (parts, body) = next.run(info, req).await?.into_parts();
+
+        Ok(CommandResponse {
+            status: parts.status,
+            headers: parts.headers,
+            latency: start.elapsed(),
+            attempts: 1,
+            result: body,
+        })
+    }
+}
+
+/// Converts unsuccessful responses to errors, decoding the [`ApiError`](crate::api::error::ApiError) if present
+fn check_status(res: CommandResponse<Bytes>) -> Result<CommandResponse<Bytes>, DriverError> {
+    if res.status.is_success() {
+        return Ok(res);
     }
+
+    Err(match deserialize_ct(&res.result, res.headers.typed_get::<ContentType>()) {
+        Ok(api_error) => DriverError::ApiError(api_error),
+        Err(_) => DriverError::GenericDriverError(res.status),
+    })
 }
 
 lazy_static::lazy_static! {
@@ -207,47 +400,66 @@ where
 use base64::engine::{general_purpose::STANDARD, Engine};
 
 impl Driver {
-    pub async fn patch_file(&self, file_id: Snowflake, offset: u64, chunk: bytes::Bytes) -> Result<u64, DriverError> {
+    /// Retrieves the status of a file upload, with `upload_offset` taken from the `upload-offset` header.
+    pub async fn file_status(&self, file_id: Snowflake) -> Result<FileStatus, DriverError> {
+        let res = self.execute_with_meta(GetFileStatus { file_id }).await?;
+
+        let mut status = res.result;
+
+        if let Some(offset) = res.headers.get(HeaderName::from_static("upload-offset")) {
+            status.upload_offset = offset.to_str()?.parse()?;
+        }
+
+        Ok(status)
+    }
+
+    pub async fn patch_file(&self, file_id: Snowflake, offset: u64, chunk: Bytes) -> Result<u64, DriverError> {
         let auth = match self.auth {
             Some(ref auth) => auth.1.clone(),
             None => return Err(DriverError::MissingAuthorization),
         };
 
-        let path = format!("{}/api/v1/file/{}", self.uri, file_id);
+        let mut path = format!("{}/api/v1", self.uri);
+        let route_start = path.len();
+
+        {
+            use std::fmt::Write;
+            write!(path, "/file/{file_id}")?;
+        }
 
         let checksum = crc32fast::hash(&chunk);
 
-        let response = self
-            .inner
-            .patch(path)
-            .header(HeaderName::from_static("authorization"), auth)
-            .header(HeaderName::from_static("upload-offset"), offset)
-            .header(
-                HeaderName::from_static("upload-checksum"),
-                format!("crc32 {}", STANDARD.encode(checksum.to_be_bytes())),
-            )
-            .header(
-                HeaderName::from_static("content-type"),
-                HeaderValue::from_static("application/offset+octet-stream"),
-            )
-            .body(chunk)
-            .send()
-            .await?;
-
-        let status = response.status();
-
-        if status.is_success() {
-            if let Some(offset) = response.headers().get(HeaderName::from_static("upload-offset")) {
+        let mut req = Request::new(Method::PATCH, Url::parse(&path)?);
+
+        let headers = req.headers_mut();
+
+        headers.insert(HeaderName::from_static("authorization"), auth);
+        headers.insert(HeaderName::from_static("upload-offset"), HeaderValue::from(offset));
+        headers.insert(
+            HeaderName::from_static("upload-checksum"),
+            HeaderValue::try_from(format!("crc32 {}", STANDARD.encode(checksum.to_be_bytes())))?,
+        );
+        headers.insert(
+            HeaderName::from_static("content-type"),
+            HeaderValue::from_static("application/offset+octet-stream"),
+        );
+
+        *req.body_mut() = Some(chunk.into());
+
+        let info = CommandInfo::PATCH_FILE;
+        let res = self.send_once(&info, &path[route_start..], req).await?;
+
+        if res.status.is_success() {
+            if let Some(offset) = res.headers.get(HeaderName::from_static("upload-offset")) {
                 return Ok(offset.to_str()?.parse()?);
             }
         }
 
-        let ct = response.headers().typed_get::<ContentType>();
-        let body = response.bytes().await?;
+        let ct = res.headers.typed_get::<ContentType>();
 
-        Err(match deserialize_ct(&body, ct) {
+        Err(match deserialize_ct(&res.result, ct) {
             Ok(api_error) => DriverError::ApiError(api_error),
-            Err(_) => DriverError::GenericDriverError(status),
+            Err(_) => DriverError::GenericDriverError(res.status),
         })
     }
 }
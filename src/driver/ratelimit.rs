@@ -0,0 +1,145 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use tokio::time::Instant;
+
+use crate::api::RateLimit;
+
+use super::DriverError;
+
+/// Behavior of the [`RateLimiter`] when a command would exceed its [`RateLimit`]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateLimitMode {
+    /// Wait until the command is allowed to proceed
+    #[default]
+    Delay,
+
+    /// Fail immediately with [`DriverError::RateLimited`]
+    Reject,
+}
+
+/// Number of buckets before expired buckets are pruned
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// Client-side rate-limiter using the Generic Cell Rate Algorithm (GCRA)
+///
+/// Buckets are tracked per command type and per route, so `CreateMessage` requests to
+/// two different rooms are limited independently, as specified by each [`Command::RATE_LIMIT`](crate::api::Command::RATE_LIMIT).
+#[derive(Debug)]
+pub struct RateLimiter {
+    mode: RateLimitMode,
+
+    /// Theoretical arrival time for each route bucket, per command
+    buckets: Mutex<HashMap<&'static str, HashMap<Box<str>, Instant>>>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        RateLimiter::new(RateLimitMode::default())
+    }
+}
+
+impl RateLimiter {
+    pub fn new(mode: RateLimitMode) -> Self {
+        RateLimiter {
+            mode,
+            buckets: Mutex::default(),
+        }
+    }
+
+    pub fn mode(&self) -> RateLimitMode {
+        self.mode
+    }
+
+    /// Acquire a slot for the given command and route, delaying or rejecting as per [`RateLimitMode`].
+    pub(crate) async fn acquire(&self, command: &'static str, route: &str, limit: RateLimit) -> Result<(), DriverError> {
+        let now = Instant::now();
+
+        // burst tolerance, how far ahead of `now` the theoretical arrival time may be
+        let tolerance = limit.emission_interval.saturating_mul(u32::try_from(limit.burst_size).unwrap_or(u32::MAX));
+
+        let wait = {
+            let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+            let buckets = buckets.entry(command).or_default();
+
+            if buckets.len() >= PRUNE_THRESHOLD {
+                buckets.retain(|_, tat| *tat > now);
+            }
+
+            let tat = match buckets.get(route) {
+                Some(&tat) if tat > now => tat,
+                _ => now,
+            };
+
+            let new_tat = tat + limit.emission_interval;
+            let wait = (new_tat - now).saturating_sub(tolerance);
+
+            if wait > Duration::ZERO && self.mode == RateLimitMode::Reject {
+                return Err(DriverError::RateLimited(wait));
+            }
+
+            // reserve the slot, even when delayed, so concurrent requests queue up behind it
+            match buckets.get_mut(route) {
+                Some(slot) => *slot = new_tat,
+                None => {
+                    buckets.insert(route.into(), new_tat);
+                }
+            }
+
+            wait
+        };
+
+        if wait > Duration::ZERO {
+            tokio::time::sleep(wait).await;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::api::{commands::room::CreateMessage, Command};
+
+    fn cmd() -> &'static str {
+        std::any::type_name::<CreateMessage>()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_delay_after_burst() {
+        let limiter = RateLimiter::new(RateLimitMode::Delay);
+        let limit = CreateMessage::RATE_LIMIT; // 100ms, burst of 2
+
+        let start = Instant::now();
+
+        limiter.acquire(cmd(), "/room/1/messages", limit).await.unwrap();
+        limiter.acquire(cmd(), "/room/1/messages", limit).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // separate route has its own bucket
+        limiter.acquire(cmd(), "/room/2/messages", limit).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        limiter.acquire(cmd(), "/room/1/messages", limit).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reject_after_burst() {
+        let limiter = RateLimiter::new(RateLimitMode::Reject);
+        let limit = CreateMessage::RATE_LIMIT;
+
+        limiter.acquire(cmd(), "/room/1/messages", limit).await.unwrap();
+        limiter.acquire(cmd(), "/room/1/messages", limit).await.unwrap();
+
+        match limiter.acquire(cmd(), "/room/1/messages", limit).await {
+            Err(DriverError::RateLimited(wait)) => assert_eq!(wait, Duration::from_millis(100)),
+            res => panic!("expected rate-limit, got {res:?}"),
+        }
+
+        tokio::time::advance(Duration::from_millis(100)).await;
+
+        limiter.acquire(cmd(), "/room/1/messages", limit).await.unwrap();
+    }
+}
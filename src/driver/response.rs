@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+use http::{HeaderMap, StatusCode};
+
+/// Successful response to a command, with the decoded result and response metadata.
+///
+/// Returned by [`Driver::execute_with_meta`](super::Driver::execute_with_meta).
+#[derive(Debug, Clone)]
+pub struct CommandResponse<T> {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+
+    /// Time taken by the final attempt, from sending the request to receiving the full response body.
+    ///
+    /// Excludes any time spent waiting on the client-side rate-limiter or between retries.
+    pub latency: Duration,
+
+    /// Number of attempts made, which may be more than one if the request was retried
+    pub attempts: u32,
+
+    pub result: T,
+}
+
+impl<T> CommandResponse<T> {
+    #[inline]
+    pub fn into_result(self) -> T {
+        self.result
+    }
+
+    /// Returns the value of the given header, if present and valid UTF-8
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)?.to_str().ok()
+    }
+
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> CommandResponse<U> {
+        CommandResponse {
+            status: self.status,
+            headers: self.headers,
+            latency: self.latency,
+            attempts: self.attempts,
+            result: f(self.result),
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use std::time::{Duration, SystemTime};
+
+use headers::{Date, Header};
+use http::{HeaderMap, Method, StatusCode};
+
+/// Policy for retrying requests that failed with a rate-limit (429), transient server error (5xx)
+/// or connection error, using exponential backoff with jitter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first. A value of `1` disables retries.
+    pub max_attempts: u32,
+
+    /// Delay before the first retry, doubled with each subsequent attempt.
+    pub base_delay: Duration,
+
+    /// Upper bound for any single delay, including those requested by the server.
+    pub max_delay: Duration,
+
+    /// Also retry non-idempotent methods, such as `POST` and `PATCH`.
+    ///
+    /// This may cause duplicate side-effects if the server processed a request
+    /// but failed to respond, so it is disabled by default.
+    pub retry_non_idempotent: bool,
+}
+
+impl RetryPolicy {
+    /// Never retry requests
+    pub const NONE: RetryPolicy = RetryPolicy {
+        max_attempts: 1,
+        ..RetryPolicy::DEFAULT
+    };
+
+    /// Default retry policy for idempotent requests
+    ///
+    /// ```ignore
+    /// RetryPolicy {
+    ///     max_attempts: 3,
+    ///     base_delay: 250ms,
+    ///     max_delay: 30s,
+    ///     retry_non_idempotent: false,
+    /// }
+    /// ```
+    pub const DEFAULT: RetryPolicy = RetryPolicy {
+        max_attempts: 3,
+        base_delay: Duration::from_millis(250),
+        max_delay: Duration::from_secs(30),
+        retry_non_idempotent: false,
+    };
+
+    /// Returns true if requests using this method may be retried
+    pub fn applies_to(&self, method: &Method) -> bool {
+        self.max_attempts > 1 && (self.retry_non_idempotent || method.is_idempotent())
+    }
+
+    /// Computes the delay before the next attempt, given the number of attempts made so far.
+    ///
+    /// If the server provided a delay, it takes precedence over the backoff.
+    pub fn delay(&self, attempts: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after {
+            return retry_after.min(self.max_delay);
+        }
+
+        let backoff = self.base_delay.saturating_mul(1 << attempts.saturating_sub(1).min(16)).min(self.max_delay);
+
+        // "equal jitter", wait at least half of the backoff
+        let half = backoff / 2;
+
+        half + half.mul_f64(fastrand::f64())
+    }
+}
+
+impl Default for RetryPolicy {
+    #[inline]
+    fn default() -> Self {
+        RetryPolicy::DEFAULT
+    }
+}
+
+/// Rate-limited or transient server errors that are worth retrying
+pub(crate) fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Parses the delay requested by the server, from either `Retry-After` or `RateLimit-Reset`,
+/// given in seconds or as an HTTP-date.
+pub(crate) fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    ["retry-after", "ratelimit-reset"].into_iter().find_map(|name| {
+        let value = headers.get(name)?;
+
+        if let Ok(secs) = value.to_str().ok()?.trim().parse::<f64>() {
+            return Duration::try_from_secs_f64(secs).ok();
+        }
+
+        let date = SystemTime::from(Date::decode(&mut std::iter::once(value)).ok()?);
+
+        // dates in the past mean no delay
+        Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_delay() {
+        let policy = RetryPolicy::DEFAULT;
+
+        for attempts in 1..10 {
+            let backoff = policy.base_delay.saturating_mul(1 << (attempts - 1)).min(policy.max_delay);
+            let delay = policy.delay(attempts, None);
+
+            assert!(backoff / 2 <= delay && delay <= backoff, "{delay:?} not within {backoff:?}");
+        }
+
+        assert_eq!(policy.delay(1, Some(Duration::from_secs(2))), Duration::from_secs(2));
+        assert_eq!(policy.delay(1, Some(Duration::from_secs(3600))), policy.max_delay);
+    }
+
+    #[test]
+    fn test_retry_methods() {
+        let policy = RetryPolicy::DEFAULT;
+
+        assert!(policy.applies_to(&Method::GET));
+        assert!(policy.applies_to(&Method::DELETE));
+        assert!(!policy.applies_to(&Method::POST));
+        assert!(!RetryPolicy::NONE.applies_to(&Method::GET));
+
+        let policy = RetryPolicy {
+            retry_non_idempotent: true,
+            ..policy
+        };
+
+        assert!(policy.applies_to(&Method::POST));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert("ratelimit-reset", "1.5".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_millis(1500)));
+
+        headers.insert("retry-after", "3".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(3)));
+
+        headers.insert("retry-after", "Sun, 06 Nov 1994 08:49:37 GMT".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+
+        // HTTP-dates only have second precision
+        let mut date = Vec::new();
+        Date::from(SystemTime::now() + Duration::from_secs(60)).encode(&mut date);
+        headers.insert("retry-after", date.pop().unwrap());
+
+        let delay = retry_after(&headers).unwrap();
+        assert!(
+            Duration::from_secs(55) < delay && delay <= Duration::from_secs(60),
+            "{delay:?}"
+        );
+
+        headers.insert("retry-after", "soon".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_millis(1500)));
+    }
+}
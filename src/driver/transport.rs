@@ -0,0 +1,155 @@
+use std::{future::Future, pin::Pin};
+
+use bytes::Bytes;
+use http::{Method, Response};
+use reqwest::Request;
+
+use crate::{
+    api::{Command, CommandFlags, RateLimit},
+    models::Permissions,
+};
+
+use super::DriverError;
+
+/// Information about the command being executed, given to the [`Transport`]
+#[derive(Debug, Clone)]
+pub struct CommandInfo {
+    /// Type name of the command, as given by [`std::any::type_name`]
+    pub name: &'static str,
+
+    /// See [`Command::HTTP_METHOD`]
+    pub method: Method,
+
+    /// See [`Command::FLAGS`]
+    pub flags: CommandFlags,
+
+    /// See [`Command::RATE_LIMIT`]
+    pub rate_limit: RateLimit,
+
+    /// See [`Command::perms`]
+    pub perms: Permissions,
+}
+
+impl CommandInfo {
+    /// Chunk uploads performed by [`Driver::patch_file`](super::Driver::patch_file), which is not a [`Command`]
+    pub const PATCH_FILE: CommandInfo = CommandInfo {
+        name: "PatchFile",
+        method: Method::PATCH,
+        flags: CommandFlags::AUTHORIZED.union(CommandFlags::HAS_BODY),
+        rate_limit: RateLimit::DEFAULT,
+        perms: Permissions::empty(),
+    };
+
+    /// Downloads performed by [`Driver::download`](super::Driver::download), which is not a [`Command`]
+    pub const DOWNLOAD: CommandInfo = CommandInfo {
+        name: "Download",
+        method: Method::GET,
+        flags: CommandFlags::empty(),
+        rate_limit: RateLimit::DEFAULT,
+        perms: Permissions::empty(),
+    };
+
+    pub fn of<CMD: Command>(cmd: &CMD) -> Self {
+        CommandInfo {
+            name: std::any::type_name::<CMD>(),
+            method: CMD::HTTP_METHOD,
+            flags: CMD::FLAGS,
+            rate_limit: CMD::RATE_LIMIT,
+            perms: cmd.perms(),
+        }
+    }
+
+    /// Returns true if this is the info for the given command type
+    pub fn is<CMD: Command>(&self) -> bool {
+        self.name == std::any::type_name::<CMD>()
+    }
+}
+
+/// Future returned by [`Transport::execute`], resolving to a fully-buffered response
+pub type TransportFuture = Pin<Box<dyn Future<Output = Result<Response<Bytes>, DriverError>> + Send + 'static>>;
+
+/// Future returned by [`Transport::download`], resolving once the response headers are received
+pub type DownloadFuture = Pin<Box<dyn Future<Output = Result<Response<DownloadBody>, DriverError>> + Send + 'static>>;
+
+/// Response body of a download, read incrementally with [`DownloadBody::chunk`]
+pub struct DownloadBody(DownloadBodyInner);
+
+enum DownloadBodyInner {
+    Streaming(reqwest::Response),
+    Buffered(Option<Bytes>),
+}
+
+impl DownloadBody {
+    /// Body that has already been fully received
+    pub fn buffered(body: Bytes) -> Self {
+        DownloadBody(DownloadBodyInner::Buffered(Some(body)))
+    }
+
+    /// Returns the next chunk of the body, or `None` once it has been fully read
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, DriverError> {
+        match self.0 {
+            DownloadBodyInner::Streaming(ref mut response) => Ok(response.chunk().await?),
+            DownloadBodyInner::Buffered(ref mut body) => Ok(body.take().filter(|body| !body.is_empty())),
+        }
+    }
+}
+
+impl From<reqwest::Response> for DownloadBody {
+    fn from(response: reqwest::Response) -> Self {
+        DownloadBody(DownloadBodyInner::Streaming(response))
+    }
+}
+
+/// HTTP transport used by the [`Driver`](super::Driver) to perform requests.
+///
+/// Implemented for [`reqwest::Client`] by default, but can be replaced to run
+/// commands against something else, such as the [`MockTransport`](super::mock::MockTransport).
+pub trait Transport: Send + Sync + 'static {
+    /// Perform the request and return the response, regardless of its status code.
+    fn execute(&self, cmd: &CommandInfo, req: Request) -> TransportFuture;
+
+    /// Perform the request and return the response once its headers are received,
+    /// with the body read incrementally, regardless of its status code.
+    ///
+    /// By default, this buffers the full body using [`execute`](Transport::execute) with [`CommandInfo::DOWNLOAD`].
+    fn download(&self, req: Request) -> DownloadFuture {
+        let response = self.execute(&CommandInfo::DOWNLOAD, req);
+
+        Box::pin(async move { Ok(response.await?.map(DownloadBody::buffered)) })
+    }
+}
+
+impl Transport for reqwest::Client {
+    fn execute(&self, _cmd: &CommandInfo, req: Request) -> TransportFuture {
+        let client = self.clone();
+
+        Box::pin(async move {
+            let response = client.execute(req).await?;
+
+            let status = response.status();
+            let headers = response.headers().clone();
+
+            let mut response = Response::new(response.bytes().await?);
+
+            *response.status_mut() = status;
+            *response.headers_mut() = headers;
+
+            Ok(response)
+        })
+    }
+
+    fn download(&self, req: Request) -> DownloadFuture {
+        let client = self.clone();
+
+        Box::pin(async move {
+            let response = client.execute(req).await?;
+
+            let mut res = Response::new(());
+
+            *res.status_mut() = response.status();
+            *res.headers_mut() = response.headers().clone();
+
+            Ok(res.map(|_| DownloadBody::from(response)))
+        })
+    }
+}
@@ -1,16 +1,10 @@
-use std::{
-    future::Future,
-    pin::Pin,
-    sync::{
-        atomic::{AtomicU32, Ordering::SeqCst},
-        Arc,
-    },
-};
+use std::{future::Future, pin::Pin, sync::Arc};
 
 use crate::{
     client::Client,
     driver::Driver,
     framework::{ServerMsg, ServerMsgHandlers},
+    gateway::GatewayConnectionControl,
     models::{gateway::message::ClientMsg, *},
 };
 
@@ -25,16 +19,20 @@ pub enum StandardResponse {
 struct StandardContextInner {
     tx: mpsc::UnboundedSender<StandardResponse>,
     client: Client,
+    gateway: Arc<GatewayConnectionControl>,
 }
 
 #[derive(Clone)]
 pub struct StandardContext(Arc<StandardContextInner>);
 
 impl StandardContext {
-    pub(super) fn new(client: Client) -> (Self, mpsc::UnboundedReceiver<StandardResponse>) {
+    pub(super) fn new(
+        client: Client,
+        gateway: Arc<GatewayConnectionControl>,
+    ) -> (Self, mpsc::UnboundedReceiver<StandardResponse>) {
         let (tx, rx) = mpsc::unbounded_channel();
 
-        (StandardContext(Arc::new(StandardContextInner { client, tx })), rx)
+        (StandardContext(Arc::new(StandardContextInner { client, tx, gateway })), rx)
     }
 
     fn inner(&self) -> &StandardContextInner {
@@ -49,6 +47,11 @@ impl StandardContext {
         self.client().driver()
     }
 
+    /// Control structure of the underlying gateway connection, which also tracks the session
+    pub fn gateway_control(&self) -> &Arc<GatewayConnectionControl> {
+        &self.inner().gateway
+    }
+
     pub fn close(&self) -> bool {
         self.inner().tx.send(StandardResponse::Close).is_ok()
     }
@@ -70,50 +73,31 @@ impl StandardContext {
     }
 }
 
-use tokio::sync::Notify;
-
+/// Internal handlers that perform the Identify handshake before passing events on to the user handlers.
+///
+/// Heartbeats are handled by the [`GatewayConnection`](crate::gateway::GatewayConnection) itself.
 pub struct InternalEventHandlers<H> {
     pub user: H,
-    heartbeat: Arc<Notify>,
-    interval: AtomicU32,
+
+    /// Intents to identify with
+    pub intent: Intent,
 }
 
 impl<H> InternalEventHandlers<H> {
     pub fn new(state: H) -> Self {
         InternalEventHandlers {
             user: state,
-            heartbeat: Default::default(),
-            interval: AtomicU32::new(45_000),
+            intent: Intent::all(),
         }
     }
 
-    #[allow(clippy::needless_return)] // ugh
-    fn setup_new_heartbeat(&self, ctx: StandardContext) {
-        let hb = self.heartbeat.clone();
-        let interval = self.interval.load(SeqCst);
-
-        hb.notify_waiters();
-        tokio::spawn(async move {
-            let duration = tokio::time::Duration::from_millis(interval as u64);
-
-            tokio::time::sleep(duration).await;
-
-            if ctx.send(ClientMsg::new_heartbeat()).is_err() {
-                return;
-            }
-
-            // once the heartbeat is sent, the server will respond with ack and
-            // trigger the killswitch to cancel this sleep, avoiding `close()`
-            // but if the server does not respond, close will be called
-
-            let mut sleep = std::pin::pin!(tokio::time::sleep(duration));
-
-            tokio::select! {
-                biased;
-                _ = hb.notified() => return,
-                _ = &mut sleep => ctx.close(),
-            };
-        });
+    fn identify(&self, ctx: &StandardContext) {
+        if let Some(auth) = ctx.client().auth() {
+            let _ = ctx.send(ClientMsg::new_identify(commands::Identify {
+                auth,
+                intent: self.intent,
+            }));
+        }
     }
 }
 
@@ -146,21 +130,15 @@ where
         'life0: 'async_trait,
         Self: 'async_trait,
     {
-        self.interval.store(inner.heartbeat_interval, SeqCst);
-
-        self.setup_new_heartbeat(ctx.clone());
-
-        if let Some(auth) = ctx.client().auth() {
-            let _ = ctx.send(ClientMsg::new_identify(commands::Identify {
-                auth,
-                intent: Intent::all(),
-            }));
+        // the gateway connection will have already sent a resume
+        if !ctx.gateway_control().is_resuming() {
+            self.identify(&ctx);
         }
 
         self.user.hello(ctx, inner)
     }
 
-    fn heartbeat_ack<'life0, 'async_trait>(
+    fn invalid_session<'life0, 'async_trait>(
         &'life0 self,
         ctx: StandardContext,
     ) -> std::pin::Pin<Box<dyn Future<Output = Result<(), E>> + Send + 'async_trait>>
@@ -168,9 +146,10 @@ where
         'life0: 'async_trait,
         Self: 'async_trait,
     {
-        self.setup_new_heartbeat(ctx.clone());
+        // the session could not be resumed, so start a new one
+        self.identify(&ctx);
 
-        self.user.heartbeat_ack(ctx)
+        self.user.invalid_session(ctx)
     }
 
     //async fn ready(&self, ctx: StandardContext, ready: Box<Ready>) -> Result<(), E> {
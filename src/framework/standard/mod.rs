@@ -5,6 +5,7 @@ use std::sync::Arc;
 use crate::{
     client::Client,
     gateway::{GatewayConnection, GatewayConnectionControl},
+    models::Intent,
 };
 
 use futures::{stream::SplitSink, SinkExt, StreamExt};
@@ -33,6 +34,8 @@ pub type SimpleStandard<H> = Standard<H, StandardError>;
 type ErrorCb<H, E> = Arc<dyn Fn(E, StandardContext, &H) + Send + Sync + 'static>;
 /// Runs once after first gateway connection is established
 type StartCb<H, E> = Box<dyn FnOnce(StandardContext, &mut H) -> Result<(), E>>;
+/// Computes the intents to identify with from the final handlers
+type IntentCb<H> = Box<dyn FnOnce(&H) -> Intent>;
 
 pub struct Standard<H, E: StandardErrorExt = StandardError> {
     state: ctx::InternalEventHandlers<H>,
@@ -40,6 +43,7 @@ pub struct Standard<H, E: StandardErrorExt = StandardError> {
     gateway: GatewayConnection,
     on_error: Option<ErrorCb<H, E>>,
     on_start: Option<StartCb<H, E>>,
+    auto_intents: Option<IntentCb<H>>,
     rx: mpsc::UnboundedReceiver<ctx::StandardResponse>,
 }
 
@@ -59,6 +63,16 @@ where
             StandardDynamicHandler::new_raw_with_state(state, Box::new(|_, _, _| Box::pin(util::ZSTOkFut::new()))),
         )
     }
+
+    /// Identify with only the intents required by the registered handlers,
+    /// as given by [`DynamicServerMsgHandlers::matching_intents`].
+    ///
+    /// The intents are computed when the framework starts running, so handlers may be registered afterwards.
+    /// Events that would only reach the fallback handler will no longer be received.
+    pub fn auto_intents(&mut self) -> &mut Self {
+        self.auto_intents = Some(Box::new(|handlers| handlers.matching_intents()));
+        self
+    }
 }
 
 impl<H: 'static, E: StandardErrorExt> Standard<H, E>
@@ -66,18 +80,30 @@ where
     H: ServerMsgHandlers<StandardContext, Result<(), E>>,
 {
     pub fn new_with_handlers(client: Client, state: H) -> Self {
-        let (ctx, rx) = StandardContext::new(client.clone());
+        let gateway = GatewayConnection::new(client.clone());
+        gateway.control().set_heartbeat(true);
+        let (ctx, rx) = StandardContext::new(client, gateway.control());
 
         Standard {
             state: ctx::InternalEventHandlers::new(state),
-            gateway: GatewayConnection::new(client),
+            gateway,
             ctx,
             rx,
             on_error: None,
             on_start: None,
+            auto_intents: None,
         }
     }
 
+    /// Set the intents to identify with, which default to [`Intent::all()`].
+    ///
+    /// Events not matching these intents will not be sent by the server.
+    pub fn set_intents(&mut self, intent: Intent) -> &mut Self {
+        self.state.intent = intent;
+        self.auto_intents = None;
+        self
+    }
+
     /// Setup a callback for any errors that occur during the connection lifetime
     pub fn on_error<F>(&mut self, cb: F) -> &mut Self
     where
@@ -118,9 +144,14 @@ where
             mut gateway,
             on_error,
             on_start,
+            auto_intents,
             rx,
         } = self;
 
+        if let Some(auto_intents) = auto_intents {
+            state.intent = auto_intents(&state.user);
+        }
+
         // connect to gateway first, split streams
         let (gw_tx, mut gw_rx) = {
             gateway.connect().await?;
@@ -0,0 +1,197 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Backoff between gateway reconnect attempts, with a circuit breaker to stop
+/// hammering the server when it is consistently unreachable.
+///
+/// See [`GatewayConnectionControl::set_backoff`](super::GatewayConnectionControl::set_backoff)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ReconnectBackoff {
+    /// Delay before the second consecutive attempt, doubled with each failure after that.
+    ///
+    /// The first reconnect after a healthy connection is made immediately.
+    pub base_delay: Duration,
+
+    /// Upper bound for any single delay
+    pub max_delay: Duration,
+
+    /// Number of consecutive failures before the circuit breaker opens
+    pub failure_threshold: u32,
+
+    /// How long the circuit breaker stays open before allowing a single trial attempt
+    pub open_duration: Duration,
+
+    /// How long a connection must stay up to be considered healthy, resetting the failure and reconnect counters
+    pub healthy_after: Duration,
+}
+
+impl ReconnectBackoff {
+    /// Default reconnect backoff
+    ///
+    /// ```ignore
+    /// ReconnectBackoff {
+    ///     base_delay: 1s,
+    ///     max_delay: 60s,
+    ///     failure_threshold: 8,
+    ///     open_duration: 5min,
+    ///     healthy_after: 60s,
+    /// }
+    /// ```
+    pub const DEFAULT: ReconnectBackoff = ReconnectBackoff {
+        base_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(60),
+        failure_threshold: 8,
+        open_duration: Duration::from_secs(60 * 5),
+        healthy_after: Duration::from_secs(60),
+    };
+
+    /// Computes the delay before the next attempt, given the number of consecutive failures so far.
+    pub fn delay(&self, failures: u32) -> Duration {
+        if failures == 0 {
+            return Duration::ZERO;
+        }
+
+        let backoff = self.base_delay.saturating_mul(1 << (failures - 1).min(16)).min(self.max_delay);
+
+        // "equal jitter", wait at least half of the backoff
+        let half = backoff / 2;
+
+        half + half.mul_f64(fastrand::f64())
+    }
+}
+
+impl Default for ReconnectBackoff {
+    #[inline]
+    fn default() -> Self {
+        ReconnectBackoff::DEFAULT
+    }
+}
+
+/// State of the reconnect circuit breaker, see [`GatewayConnectionControl::circuit_state`](super::GatewayConnectionControl::circuit_state)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CircuitState {
+    /// Reconnecting normally, with backoff between failed attempts
+    Closed,
+
+    /// Too many consecutive failures, so no attempts will be made until the open duration has passed
+    Open,
+
+    /// A single trial attempt is being made after being open, which will close the circuit if it succeeds
+    HalfOpen,
+}
+
+#[derive(Debug)]
+pub(crate) struct CircuitBreaker {
+    pub backoff: ReconnectBackoff,
+    pub state: CircuitState,
+    pub failures: u32,
+    opened_at: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(backoff: ReconnectBackoff) -> Self {
+        CircuitBreaker {
+            backoff,
+            state: CircuitState::Closed,
+            failures: 0,
+            opened_at: None,
+        }
+    }
+
+    /// Returns how long to wait before the next connection attempt
+    pub fn next_delay(&mut self, now: Instant) -> Duration {
+        match self.state {
+            CircuitState::Open => {
+                self.state = CircuitState::HalfOpen;
+
+                let reopen = self.opened_at.unwrap_or(now) + self.backoff.open_duration;
+
+                reopen.saturating_duration_since(now)
+            }
+            _ => self.backoff.delay(self.failures),
+        }
+    }
+
+    /// A connection attempt failed, or the connection dropped before becoming healthy
+    pub fn on_failure(&mut self, now: Instant) {
+        self.failures = self.failures.saturating_add(1);
+
+        if self.state == CircuitState::HalfOpen || self.failures >= self.backoff.failure_threshold {
+            self.state = CircuitState::Open;
+            self.opened_at = Some(now);
+        }
+    }
+
+    /// A connection was established, which closes the circuit but keeps counting
+    /// failures until the connection becomes healthy.
+    pub fn on_connected(&mut self) {
+        self.state = CircuitState::Closed;
+        self.opened_at = None;
+    }
+
+    /// The connection has been up for long enough to be considered healthy
+    pub fn on_healthy(&mut self) {
+        self.on_connected();
+        self.failures = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let backoff = ReconnectBackoff::DEFAULT;
+
+        assert_eq!(backoff.delay(0), Duration::ZERO);
+
+        for failures in 1..10 {
+            let max = backoff.base_delay.saturating_mul(1 << (failures - 1)).min(backoff.max_delay);
+            let delay = backoff.delay(failures);
+
+            assert!(max / 2 <= delay && delay <= max, "{delay:?} not within {max:?}");
+        }
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let backoff = ReconnectBackoff {
+            failure_threshold: 3,
+            ..ReconnectBackoff::DEFAULT
+        };
+
+        let mut breaker = CircuitBreaker::new(backoff);
+        let now = Instant::now();
+
+        assert_eq!(breaker.next_delay(now), Duration::ZERO);
+
+        for _ in 0..3 {
+            assert_eq!(breaker.state, CircuitState::Closed);
+            breaker.next_delay(now);
+            breaker.on_failure(now);
+        }
+
+        assert_eq!(breaker.state, CircuitState::Open);
+
+        // waits out the rest of the open duration, then makes a trial attempt
+        let later = now + Duration::from_secs(60);
+        assert_eq!(breaker.next_delay(later), backoff.open_duration - Duration::from_secs(60));
+        assert_eq!(breaker.state, CircuitState::HalfOpen);
+
+        // trial failed, so opens again immediately
+        breaker.on_failure(later);
+        assert_eq!(breaker.state, CircuitState::Open);
+        assert_eq!(breaker.next_delay(later), backoff.open_duration);
+
+        // trial succeeded, but still backs off until healthy
+        breaker.on_connected();
+        assert_eq!(breaker.state, CircuitState::Closed);
+        assert!(breaker.next_delay(later) > Duration::ZERO);
+
+        breaker.on_healthy();
+        assert_eq!(breaker.failures, 0);
+        assert_eq!(breaker.next_delay(later), Duration::ZERO);
+    }
+}
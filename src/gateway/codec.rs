@@ -0,0 +1,299 @@
+use std::sync::Arc;
+
+use miniz_oxide::deflate::{core::CompressorOxide, stream::deflate};
+use miniz_oxide::inflate::stream::{inflate, InflateState};
+use miniz_oxide::{DataFormat, MZError, MZFlush, MZStatus};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+use crate::driver::Encoding;
+use crate::models::gateway::message::{ClientMsg, ServerMsg};
+
+use super::error::GatewayErrorCode;
+use super::{GatewayConfig, GatewayError, GatewayStats};
+
+/// Buffers larger than this are released after use rather than kept for the next message
+const MAX_RETAINED_BUFFER: usize = 1024 * 1024;
+
+/// zlib compressor, either starting a new stream for each message or
+/// continuing a single stream with a sync-flush after each message.
+pub(crate) struct Deflater {
+    compressor: Box<CompressorOxide>,
+    stream: bool,
+}
+
+impl Deflater {
+    pub fn new(level: u8, stream: bool) -> Self {
+        let mut compressor = Box::<CompressorOxide>::default();
+        compressor.set_format_and_level(DataFormat::Zlib, level);
+
+        Deflater { compressor, stream }
+    }
+
+    /// Compresses `input` into `out`, replacing its contents. Returns false if compression failed.
+    pub fn compress(&mut self, mut input: &[u8], out: &mut Vec<u8>) -> bool {
+        let flush = match self.stream {
+            true => MZFlush::Sync,
+            false => {
+                self.compressor.reset();
+                MZFlush::Finish
+            }
+        };
+
+        // leave enough room for incompressible input, to avoid repeated flush markers
+        out.clear();
+        out.resize(input.len() + input.len() / 16 + 64, 0);
+
+        let mut pos = 0;
+
+        loop {
+            let res = deflate(&mut self.compressor, input, &mut out[pos..], flush);
+
+            input = &input[res.bytes_consumed..];
+            pos += res.bytes_written;
+
+            match res.status {
+                Ok(MZStatus::StreamEnd) => break,
+                Ok(_) if input.is_empty() && pos < out.len() => break,
+                Ok(_) => out.resize(out.len() * 2, 0),
+                Err(_) => return false,
+            }
+        }
+
+        out.truncate(pos);
+
+        true
+    }
+}
+
+/// zlib decompressor, the counterpart to [`Deflater`]
+pub(crate) struct Inflater {
+    state: Box<InflateState>,
+    stream: bool,
+}
+
+impl Inflater {
+    pub fn new(stream: bool) -> Self {
+        Inflater {
+            state: InflateState::new_boxed(DataFormat::Zlib),
+            stream,
+        }
+    }
+
+    /// Decompresses `input` into `out`, replacing its contents. Returns false if the data was invalid or incomplete.
+    pub fn decompress(&mut self, mut input: &[u8], out: &mut Vec<u8>) -> bool {
+        if !self.stream {
+            self.state.reset(DataFormat::Zlib);
+        }
+
+        out.clear();
+        out.resize((input.len() * 4).max(1024), 0);
+
+        let mut pos = 0;
+
+        loop {
+            let res = inflate(&mut self.state, input, &mut out[pos..], MZFlush::None);
+
+            input = &input[res.bytes_consumed..];
+            pos += res.bytes_written;
+
+            let has_room = pos < out.len();
+
+            match res.status {
+                // a stream may only end if each message is its own stream
+                Ok(MZStatus::StreamEnd) if !self.stream => break,
+                Ok(MZStatus::Ok) | Err(MZError::Buf) if input.is_empty() && has_room => {
+                    // messages must be complete streams unless streaming
+                    if !self.stream {
+                        return false;
+                    }
+
+                    break;
+                }
+                Ok(MZStatus::Ok) if !has_room => out.resize(out.len() * 2, 0),
+                Ok(MZStatus::Ok) => {}
+                _ => return false,
+            }
+        }
+
+        out.truncate(pos);
+
+        true
+    }
+}
+
+/// Encodes and decodes gateway messages according to the [`GatewayConfig`],
+/// reusing the compression contexts and buffers across messages.
+pub(crate) struct GatewayCodec {
+    pub config: GatewayConfig,
+    pub stats: Option<Arc<GatewayStats>>,
+    deflater: Option<Deflater>,
+    inflater: Option<Inflater>,
+    buffer: Vec<u8>,
+}
+
+impl GatewayCodec {
+    pub fn new(config: GatewayConfig) -> Self {
+        GatewayCodec {
+            config,
+            stats: None,
+            deflater: config.compress.then(|| Deflater::new(config.level(), config.stream)),
+            inflater: config.compress.then(|| Inflater::new(config.stream)),
+            buffer: Vec::new(),
+        }
+    }
+
+    fn release_buffer(&mut self) {
+        if self.buffer.capacity() > MAX_RETAINED_BUFFER {
+            self.buffer = Vec::new();
+        }
+    }
+
+    pub fn encode(&mut self, msg: &ClientMsg) -> Result<WsMessage, GatewayError> {
+        self.buffer.clear();
+
+        match self.config.encoding {
+            Encoding::JSON => serde_json::to_writer(&mut self.buffer, msg)?,
+            #[cfg(feature = "cbor")]
+            Encoding::CBOR => ciborium::ser::into_writer(msg, &mut self.buffer)?,
+        }
+
+        let uncompressed = self.buffer.len();
+
+        let body = match self.deflater {
+            Some(ref mut deflater) => {
+                let mut body = Vec::new();
+
+                if !deflater.compress(&self.buffer, &mut body) {
+                    return Err(GatewayError::CompressionError);
+                }
+
+                self.release_buffer();
+
+                body
+            }
+            // the message takes ownership anyway, so there is nothing to reuse
+            None => std::mem::take(&mut self.buffer),
+        };
+
+        if let Some(ref stats) = self.stats {
+            stats.record_sent(body.len(), uncompressed);
+        }
+
+        Ok(WsMessage::Binary(body))
+    }
+
+    pub fn decode(&mut self, msg: WsMessage) -> Result<ServerMsg, GatewayError> {
+        match &msg {
+            WsMessage::Close(None) => return Err(GatewayError::Disconnected),
+            WsMessage::Close(Some(msg)) => {
+                return Err(match msg.code {
+                    CloseCode::Library(code) => {
+                        use num_traits::FromPrimitive;
+
+                        GatewayError::CloseError(match GatewayErrorCode::from_u16(code) {
+                            Some(code) => code,
+                            None => GatewayErrorCode::UnknownError,
+                        })
+                    }
+                    _ => GatewayError::Disconnected,
+                });
+            }
+            _ => {}
+        }
+
+        let data = msg.into_data();
+        let compressed = data.len();
+
+        let body = match self.inflater {
+            Some(ref mut inflater) => {
+                if !inflater.decompress(&data, &mut self.buffer) {
+                    if let Some(ref stats) = self.stats {
+                        stats.record_decode_error();
+                    }
+
+                    return Err(GatewayError::CompressionError);
+                }
+
+                &self.buffer
+            }
+            None => &data,
+        };
+
+        let res = match self.config.encoding {
+            Encoding::JSON => serde_json::from_slice(body).map_err(GatewayError::from),
+            #[cfg(feature = "cbor")]
+            Encoding::CBOR => ciborium::de::from_reader(&body[..]).map_err(GatewayError::from),
+        };
+
+        let res = match res {
+            Ok(ServerMsg::Unknown(msg)) if self.config.strict => Err(GatewayError::UnknownOpcode(msg.opcode)),
+            res => res,
+        };
+
+        if let Some(ref stats) = self.stats {
+            match res {
+                Ok(_) => stats.record_received(compressed, body.len()),
+                Err(_) => stats.record_decode_error(),
+            }
+        }
+
+        self.release_buffer();
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zlib_stream() {
+        let messages: [&[u8]; 3] = [
+            br#"{"o":0,"p":{"hb":45000}}"#,
+            br#"{"o":1,"p":{"user":{"id":"1","username":"test"}}}"#,
+            br#"{"o":1,"p":{"user":{"id":"2","username":"test"}}}"#,
+        ];
+
+        for stream in [false, true] {
+            let mut deflater = Deflater::new(9, stream);
+            let mut inflater = Inflater::new(stream);
+
+            let mut compressed = Vec::new();
+            let mut decompressed = Vec::new();
+
+            for msg in messages {
+                assert!(deflater.compress(msg, &mut compressed));
+
+                if !stream {
+                    // each message is a standalone zlib stream
+                    assert_eq!(miniz_oxide::inflate::decompress_to_vec_zlib(&compressed).unwrap(), msg);
+                }
+
+                assert!(inflater.decompress(&compressed, &mut decompressed));
+                assert_eq!(decompressed, msg);
+            }
+        }
+
+        // a message cut short is rejected
+        let mut compressed = Vec::new();
+        assert!(Deflater::new(9, false).compress(messages[1], &mut compressed));
+        assert!(!Inflater::new(false).decompress(&compressed[..compressed.len() / 2], &mut Vec::new()));
+    }
+
+    #[test]
+    fn test_strict_decoding() {
+        let msg = || WsMessage::Text(r#"{"o":200,"p":{}}"#.to_owned());
+
+        let mut codec = GatewayCodec::new(GatewayConfig {
+            compress: false,
+            ..GatewayConfig::DEFAULT
+        });
+
+        assert!(matches!(codec.decode(msg()), Ok(ServerMsg::Unknown(_))));
+
+        codec.config.strict = true;
+        assert!(matches!(codec.decode(msg()), Err(GatewayError::UnknownOpcode(200))));
+    }
+}
@@ -0,0 +1,113 @@
+use crate::api::gateway::GatewayQueryParams;
+use crate::driver::Encoding;
+
+/// Encoding and compression of gateway messages, see [`GatewayConnection::with_config`](super::GatewayConnection::with_config)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GatewayConfig {
+    /// Encoding method for each individual websocket message
+    pub encoding: Encoding,
+
+    /// Whether to compress individual messages, in both directions
+    pub compress: bool,
+
+    /// Whether to compress the whole connection as a single zlib stream with a sync-flush after each message,
+    /// rather than compressing each message individually. Only used if `compress` is true.
+    ///
+    /// This compresses much better, as messages can refer back to previous ones, but requires server support.
+    pub stream: bool,
+
+    /// zlib compression level for outgoing messages, from 0 (none) to 10 (best).
+    ///
+    /// Messages sent to the gateway are small, so this mostly affects CPU usage rather than bandwidth.
+    pub compression_level: u8,
+
+    /// Whether to fail with [`GatewayError::UnknownOpcode`](super::GatewayError::UnknownOpcode) on messages with
+    /// an opcode unknown to this version, rather than passing them on as [`ServerMsg::Unknown`].
+    ///
+    /// Useful in tests to catch protocol mismatches early.
+    ///
+    /// [`ServerMsg::Unknown`]: crate::models::gateway::message::ServerMsg::Unknown
+    pub strict: bool,
+}
+
+impl GatewayConfig {
+    /// Default gateway configuration
+    ///
+    /// ```ignore
+    /// GatewayConfig {
+    ///     encoding: Encoding::JSON,
+    ///     compress: true,
+    ///     stream: false,
+    ///     compression_level: 9,
+    ///     strict: false,
+    /// }
+    /// ```
+    pub const DEFAULT: GatewayConfig = GatewayConfig {
+        encoding: Encoding::JSON,
+        compress: true,
+        stream: false,
+        compression_level: 9,
+        strict: false,
+    };
+
+    /// Maximum zlib compression level
+    pub const MAX_COMPRESSION_LEVEL: u8 = 10;
+
+    /// Query parameters sent to the gateway when connecting
+    pub fn query_params(&self) -> GatewayQueryParams {
+        GatewayQueryParams {
+            encoding: self.encoding,
+            compress: self.compress,
+            stream: self.stream,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn level(&self) -> u8 {
+        self.compression_level.min(Self::MAX_COMPRESSION_LEVEL)
+    }
+}
+
+impl Default for GatewayConfig {
+    #[inline]
+    fn default() -> Self {
+        GatewayConfig::DEFAULT
+    }
+}
+
+impl From<GatewayQueryParams> for GatewayConfig {
+    fn from(params: GatewayQueryParams) -> Self {
+        GatewayConfig {
+            encoding: params.encoding,
+            compress: params.compress,
+            stream: params.stream,
+            ..GatewayConfig::DEFAULT
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gateway_query_params() {
+        let config = GatewayConfig {
+            compress: false,
+            compression_level: 20,
+            ..GatewayConfig::DEFAULT
+        };
+
+        assert_eq!(config.level(), GatewayConfig::MAX_COMPRESSION_LEVEL);
+        assert_eq!(
+            GatewayConfig::from(config.query_params()),
+            GatewayConfig {
+                compress: false,
+                ..GatewayConfig::DEFAULT
+            }
+        );
+
+        let query = serde_urlencoded::to_string(config.query_params()).unwrap();
+        assert_eq!(query, "encoding=json&compress=false&stream=false");
+    }
+}
@@ -1,15 +1,27 @@
+use std::collections::VecDeque;
 use std::num::NonZeroUsize;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 use futures::{future::BoxFuture, FutureExt, Sink, SinkExt, Stream};
+use tokio::time::Instant;
 
 use crate::client::Client;
-use crate::models::gateway::message::{ClientMsg, ServerMsg};
-
-use super::{GatewayError, GatewaySocket};
+use crate::models::{
+    gateway::message::{ClientMsg, ServerMsg},
+    Snowflake,
+};
+
+use super::{
+    backoff::CircuitBreaker,
+    heartbeat::{Heartbeat, HeartbeatTick},
+    session::{SessionTracker, SessionUpdate},
+    CircuitState, DefaultReconnectPolicy, GatewayConfig, GatewayError, GatewayRecorder, GatewaySocket, GatewayStats,
+    ReconnectAction, ReconnectBackoff, ReconnectPolicy, SessionStart,
+};
 
 /// Gateway connection that provides automatic reconnect
 /// functionality as part of the [Sink]/[Stream] APIs.
@@ -17,27 +29,59 @@ use super::{GatewayError, GatewaySocket};
 /// However, it does not automatically perform the [Hello](ServerMsg::Hello)/[Identify](ClientMsg::Identify) handshake.
 ///
 /// Upon reconnecting the underlying websocket, the server will send
-/// a [Hello](ServerMsg::Hello) event to initiate the handshake.
+/// a [Hello](ServerMsg::Hello) event to initiate the handshake. If a session was previously established,
+/// a [Resume](ClientMsg::Resume) is sent automatically in response, and [`GatewayConnectionControl::is_resuming`]
+/// will be true when the [Hello](ServerMsg::Hello) is received, in which case Identify should be skipped.
+/// Should the server reject it with [InvalidSession](ServerMsg::InvalidSession), the session is forgotten
+/// and a new one must be identified.
 ///
 /// Any errors that occur will still be passed through, and must be handled appropriately. Spamming
-/// servers will reconnections will lead to rate-limiting and possibly automated bans.
+/// servers will reconnections will lead to rate-limiting and possibly automated bans, so reconnect
+/// attempts are delayed according to the [`ReconnectBackoff`], see [`GatewayConnectionControl::set_backoff`].
+///
+/// Whether and how to reconnect after each error is decided by the [`ReconnectPolicy`],
+/// see [`GatewayConnectionControl::set_reconnect_policy`].
+///
+/// If enabled with [`GatewayConnectionControl::set_heartbeat`], heartbeats are sent automatically
+/// at the interval given by [Hello](ServerMsg::Hello) while the stream is being polled, and the connection
+/// is dropped with [`GatewayError::HeartbeatTimeout`] if one is not acknowledged in time.
 pub struct GatewayConnection {
     client: Client,
+    config: GatewayConfig,
     connecting: Option<BoxFuture<'static, Result<GatewaySocket, GatewayError>>>,
     socket: Option<GatewaySocket>,
     control: Arc<GatewayConnectionControl>,
+
+    /// Messages sent by the connection itself, ahead of any others
+    outbox: VecDeque<ClientMsg>,
+    flushing: bool,
+
+    /// When the current socket will be considered healthy, if it hasn't been yet
+    healthy_at: Option<Instant>,
+
+    /// Extra delay before the next connection attempt, given by [`ReconnectAction::Backoff`]
+    extra_delay: Duration,
+
+    heartbeat: Option<Heartbeat>,
 }
 
 pub struct GatewayConnectionControl {
     closed: AtomicBool,
     reconnects: AtomicUsize,
     reconnect_limit: AtomicUsize,
+    session: Mutex<SessionTracker>,
+    breaker: Mutex<CircuitBreaker>,
+    policy: Mutex<Arc<dyn ReconnectPolicy>>,
+    heartbeat: AtomicBool,
+    stats: Arc<GatewayStats>,
+    recorder: Mutex<Option<Arc<GatewayRecorder>>>,
 }
 
 impl GatewayConnectionControl {
-    /// Resets the connection attempt counter and opens up for new connections.
+    /// Resets the connection attempt counter and circuit breaker, and opens up for new connections.
     pub fn reset(&self) {
         self.reconnects.store(0, Ordering::SeqCst);
+        self.breaker().on_healthy();
         self.closed.store(false, Ordering::SeqCst);
     }
 
@@ -51,19 +95,126 @@ impl GatewayConnectionControl {
     pub fn noreconnect(&self) {
         self.closed.store(true, Ordering::SeqCst);
     }
+
+    fn breaker(&self) -> MutexGuard<'_, CircuitBreaker> {
+        self.breaker.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Sets the backoff used between reconnect attempts, taking effect on the next attempt.
+    pub fn set_backoff(&self, backoff: ReconnectBackoff) {
+        self.breaker().backoff = backoff;
+    }
+
+    pub fn backoff(&self) -> ReconnectBackoff {
+        self.breaker().backoff
+    }
+
+    /// Current state of the reconnect circuit breaker
+    pub fn circuit_state(&self) -> CircuitState {
+        self.breaker().state
+    }
+
+    /// Number of consecutive failed connection attempts, or connections that dropped before becoming healthy
+    pub fn consecutive_failures(&self) -> u32 {
+        self.breaker().failures
+    }
+
+    /// Sets the policy deciding whether and how to reconnect after each error
+    pub fn set_reconnect_policy(&self, policy: impl ReconnectPolicy) {
+        *self.policy.lock().unwrap_or_else(|e| e.into_inner()) = Arc::new(policy);
+    }
+
+    fn reconnect_policy(&self) -> Arc<dyn ReconnectPolicy> {
+        self.policy.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Enables or disables automatic heartbeats, starting from the next [Hello](ServerMsg::Hello).
+    pub fn set_heartbeat(&self, enabled: bool) {
+        self.heartbeat.store(enabled, Ordering::SeqCst);
+    }
+
+    /// Round-trip latency of the last acknowledged heartbeat, if automatic heartbeats are enabled
+    pub fn latency(&self) -> Option<Duration> {
+        self.stats.latency()
+    }
+
+    /// Connection health metrics, which can be shared with and read from other tasks
+    pub fn stats(&self) -> Arc<GatewayStats> {
+        self.stats.clone()
+    }
+
+    /// Records all messages sent and received to the given recorder from now on, or stops recording if `None`.
+    pub fn set_recorder(&self, recorder: Option<Arc<GatewayRecorder>>) {
+        *self.recorder.lock().unwrap_or_else(|e| e.into_inner()) = recorder;
+    }
+
+    fn recorder(&self) -> Option<Arc<GatewayRecorder>> {
+        self.recorder.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn session_tracker(&self) -> MutexGuard<'_, SessionTracker> {
+        self.session.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Session ID given by the last [Ready](ServerMsg::Ready), which will be resumed after reconnecting.
+    pub fn session(&self) -> Option<Snowflake> {
+        self.session_tracker().session
+    }
+
+    /// How the current session was established, if it has been.
+    ///
+    /// After a reconnect, this is [`SessionStart::Resumed`] if the session was resumed and cached state is still valid,
+    /// or [`SessionStart::Identified`] if a new session was started and cached state should be rebuilt.
+    pub fn session_start(&self) -> Option<SessionStart> {
+        self.session_tracker().start
+    }
+
+    /// Returns true if a [Resume](ClientMsg::Resume) has been sent on the current connection
+    /// and not yet accepted or rejected by the server.
+    pub fn is_resuming(&self) -> bool {
+        self.session_tracker().resuming
+    }
+
+    /// Forget the current session, so the next connection must Identify
+    pub fn clear_session(&self) {
+        self.session_tracker().clear();
+    }
 }
 
 impl GatewayConnection {
+    /// Constructs a new connection with the client's preferred encoding and default compression
     pub fn new(client: Client) -> GatewayConnection {
+        let config = GatewayConfig {
+            encoding: client.driver().encoding,
+            ..GatewayConfig::DEFAULT
+        };
+
+        Self::with_config(client, config)
+    }
+
+    /// Constructs a new connection with the given encoding and compression, used for every reconnect
+    pub fn with_config(client: Client, config: GatewayConfig) -> GatewayConnection {
         GatewayConnection {
             client,
+            config,
             connecting: None,
             socket: None,
             control: Arc::new(GatewayConnectionControl {
                 closed: AtomicBool::new(false),
                 reconnects: AtomicUsize::new(0),
                 reconnect_limit: AtomicUsize::new(20),
+                session: Mutex::default(),
+                breaker: Mutex::new(CircuitBreaker::new(ReconnectBackoff::DEFAULT)),
+                policy: Mutex::new(Arc::new(DefaultReconnectPolicy)),
+                heartbeat: AtomicBool::new(false),
+                stats: Arc::default(),
+                recorder: Mutex::new(None),
             }),
+            outbox: VecDeque::new(),
+            flushing: false,
+            healthy_at: None,
+            extra_delay: Duration::ZERO,
+            heartbeat: None,
         }
     }
 
@@ -74,6 +225,11 @@ impl GatewayConnection {
         futures::future::poll_fn(move |cx| self.poll_project_socket(cx).map_ok(|_| ())).await
     }
 
+    #[inline]
+    pub fn config(&self) -> &GatewayConfig {
+        &self.config
+    }
+
     /// Get a reference to the control structure
     pub fn control(&self) -> Arc<GatewayConnectionControl> {
         self.control.clone()
@@ -105,16 +261,42 @@ impl GatewayConnection {
                 return Poll::Ready(Err(GatewayError::ReconnectLimitExceeded(limit)));
             }
 
-            self.connecting = Some(GatewaySocket::connect(self.client.driver()).boxed());
+            let delay = self.control.breaker().next_delay(Instant::now());
+            let delay = delay.max(std::mem::take(&mut self.extra_delay));
+            let connect = GatewaySocket::connect_with_config(self.client.driver(), self.config);
+            let stats = self.control.stats.clone();
+
+            self.connecting = Some(
+                async move {
+                    if !delay.is_zero() {
+                        tokio::time::sleep(delay).await;
+                    }
+
+                    connect.await.map(|socket| socket.with_stats(stats))
+                }
+                .boxed(),
+            );
         }
 
         match self.connecting {
             Some(ref mut connecting) => match connecting.poll_unpin(cx) {
                 Poll::Pending => Poll::Pending,
-                Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+                Poll::Ready(Err(e)) => {
+                    self.connecting = None;
+                    self.control.breaker().on_failure(Instant::now());
+                    self.apply_policy(&e);
+
+                    Poll::Ready(Err(e))
+                }
                 Poll::Ready(Ok(socket)) => {
                     self.socket = Some(socket);
                     self.connecting = None;
+                    self.control.stats.record_connect();
+
+                    let mut breaker = self.control.breaker();
+                    breaker.on_connected();
+                    self.healthy_at = Some(Instant::now() + breaker.backoff.healthy_after);
+                    drop(breaker);
 
                     Poll::Ready(Ok(match self.socket {
                         // just assigned, project
@@ -127,20 +309,171 @@ impl GatewayConnection {
             None => unsafe { std::hint::unreachable_unchecked() },
         }
     }
+
+    /// Decides how to reconnect after the given error
+    fn apply_policy(&mut self, error: &GatewayError) {
+        match self.control.reconnect_policy().on_error(error) {
+            ReconnectAction::Resume => {}
+            ReconnectAction::Identify => self.control.clear_session(),
+            ReconnectAction::Backoff(delay) => self.extra_delay = self.extra_delay.max(delay),
+            ReconnectAction::Stop => self.control.noreconnect(),
+        }
+    }
+
+    /// Drops the current socket after an error, applying the reconnect policy if it was still connected
+    fn fail(&mut self, error: &GatewayError) {
+        if self.socket.is_some() {
+            self.apply_policy(error);
+        }
+
+        self.drop_socket();
+    }
+
+    /// Drops the current socket, along with anything left to send on it
+    fn drop_socket(&mut self) {
+        if self.socket.take().is_some() {
+            self.check_healthy();
+
+            // dropped before becoming healthy, so count it towards the backoff unless closed intentionally
+            if self.healthy_at.take().is_some() && !self.control.closed.load(Ordering::SeqCst) {
+                self.control.breaker().on_failure(Instant::now());
+            }
+        }
+
+        self.outbox.clear();
+        self.flushing = false;
+        self.heartbeat = None;
+    }
+
+    /// Resets the reconnect counters once the current socket has been up for long enough
+    fn check_healthy(&mut self) {
+        if let Some(healthy_at) = self.healthy_at {
+            if Instant::now() >= healthy_at {
+                self.healthy_at = None;
+                self.control.reconnects.store(0, Ordering::SeqCst);
+                self.control.breaker().on_healthy();
+            }
+        }
+    }
+
+    /// Sends heartbeats when due, or returns false if the last one was not acknowledged in time
+    fn poll_heartbeat(&mut self, cx: &mut Context<'_>) -> bool {
+        if let Some(ref mut heartbeat) = self.heartbeat {
+            // poll until pending, so the timer is registered with the context again
+            while let Poll::Ready(tick) = heartbeat.poll_tick(cx) {
+                match tick {
+                    HeartbeatTick::Send => self.outbox.push_back(ClientMsg::new_heartbeat()),
+                    HeartbeatTick::Timeout => return false,
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Starts heartbeating on [Hello](ServerMsg::Hello) and tracks acks, if enabled
+    fn observe_heartbeat(&mut self, msg: &ServerMsg) {
+        match msg {
+            ServerMsg::Hello(hello) if self.control.heartbeat.load(Ordering::SeqCst) => {
+                let interval = Duration::from_millis(hello.inner.heartbeat_interval as u64);
+
+                self.heartbeat = Some(Heartbeat::new(interval));
+            }
+            ServerMsg::HeartbeatAck(_) => {
+                if let Some(latency) = self.heartbeat.as_mut().and_then(Heartbeat::on_ack) {
+                    self.control.stats.record_latency(latency);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Sends any messages queued by the connection itself on the current socket
+    fn poll_outbox(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), GatewayError>> {
+        let socket = match self.socket {
+            Some(ref mut socket) => socket,
+            None => return Poll::Ready(Ok(())),
+        };
+
+        while !self.outbox.is_empty() {
+            futures::ready!(socket.poll_ready_unpin(cx))?;
+
+            if let Some(msg) = self.outbox.pop_front() {
+                Pin::new(&mut *socket).start_send_ref(&msg)?;
+                self.flushing = true;
+
+                if let Some(recorder) = self.control.recorder() {
+                    recorder.record_sent(&msg);
+                }
+            }
+        }
+
+        if self.flushing {
+            futures::ready!(socket.poll_flush_unpin(cx))?;
+            self.flushing = false;
+        }
+
+        Poll::Ready(Ok(()))
+    }
 }
 
 impl Stream for GatewayConnection {
     type Item = Result<ServerMsg, GatewayError>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if !self.poll_heartbeat(cx) {
+            let e = GatewayError::HeartbeatTimeout;
+            self.fail(&e);
+            return Poll::Ready(Some(Err(e)));
+        }
+
+        // make progress on sending any queued messages, but don't wait on it to receive
+        if let Poll::Ready(Err(e)) = self.poll_outbox(cx) {
+            self.fail(&e);
+            return Poll::Ready(Some(Err(e)));
+        }
+
         let res = match self.poll_project_socket(cx) {
             Poll::Pending => return Poll::Pending,
             Poll::Ready(Ok(socket)) => futures::ready!(socket.poll_next(cx)),
             Poll::Ready(Err(e)) => Some(Err(e)),
         };
 
-        if let None | Some(Err(_)) = res {
-            self.socket = None; // drop socket
+        match res {
+            None => self.fail(&GatewayError::Disconnected),
+            Some(Err(ref e)) => self.fail(e),
+            Some(Ok(ref msg)) => {
+                self.check_healthy();
+                self.observe_heartbeat(msg);
+
+                if let Some(recorder) = self.control.recorder() {
+                    recorder.record_received(msg);
+                }
+
+                let update = self.control.session_tracker().on_message(msg);
+
+                let resume = match update {
+                    Some(SessionUpdate::Resume(resume)) => Some(resume),
+                    Some(SessionUpdate::Identified) => {
+                        self.control.stats.record_identify();
+                        None
+                    }
+                    Some(SessionUpdate::Resumed) => {
+                        self.control.stats.record_resume();
+                        None
+                    }
+                    None => None,
+                };
+
+                if let Some(resume) = resume {
+                    self.outbox.push_back(resume);
+
+                    if let Poll::Ready(Err(e)) = self.poll_outbox(cx) {
+                        self.fail(&e);
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                }
+            }
         }
 
         Poll::Ready(res)
@@ -151,14 +484,18 @@ impl Sink<ClientMsg> for GatewayConnection {
     type Error = GatewayError;
 
     fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), GatewayError>> {
-        let res = match self.poll_project_socket(cx) {
-            Poll::Pending => return Poll::Pending,
-            Poll::Ready(Ok(socket)) => futures::ready!(socket.poll_ready(cx)),
-            Poll::Ready(Err(err)) => Err(err),
+        // queued messages must be sent first
+        let res = match futures::ready!(self.poll_outbox(cx)) {
+            Ok(()) => match self.poll_project_socket(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok(socket)) => futures::ready!(socket.poll_ready(cx)),
+                Poll::Ready(Err(err)) => Err(err),
+            },
+            Err(err) => Err(err),
         };
 
-        if res.is_err() {
-            self.socket = None; // drop socket
+        if let Err(ref e) = res {
+            self.fail(e);
         }
 
         Poll::Ready(res)
@@ -167,10 +504,20 @@ impl Sink<ClientMsg> for GatewayConnection {
     #[inline]
     fn start_send(mut self: Pin<&mut Self>, item: ClientMsg) -> Result<(), GatewayError> {
         match self.socket {
-            Some(ref mut socket) => socket.start_send_unpin(item).map_err(|err| {
-                self.socket = None; // drop socket
-                err
-            }),
+            // only record messages that were actually sent
+            Some(ref mut socket) => match Pin::new(socket).start_send_ref(&item) {
+                Ok(()) => {
+                    if let Some(recorder) = self.control.recorder() {
+                        recorder.record_sent(&item);
+                    }
+
+                    Ok(())
+                }
+                Err(err) => {
+                    self.fail(&err);
+                    Err(err)
+                }
+            },
             // `start_send` doesn't poll or have a context, so there is no way to initiate the reconnect
             None => Err(GatewayError::Disconnected),
         }
@@ -182,23 +529,24 @@ impl Sink<ClientMsg> for GatewayConnection {
             Err(e) => Err(e),
         };
 
-        if res.is_err() {
-            self.socket = None; // drop socket
+        if let Err(ref e) = res {
+            self.fail(e);
         }
 
         Poll::Ready(res)
     }
 
     fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), GatewayError>> {
-        // ensure it won't reconnect automatically
+        // ensure it won't reconnect automatically, nor resume if reconnected later
         self.control.closed.store(true, Ordering::SeqCst);
+        self.control.clear_session();
 
         let res = match futures::ready!(self.poll_project_socket(cx)) {
             Ok(socket) => futures::ready!(socket.poll_close(cx)),
             Err(e) => Err(e),
         };
 
-        self.socket = None; // drop socket
+        self.drop_socket();
 
         Poll::Ready(res)
     }
@@ -5,12 +5,18 @@ pub enum GatewayError {
     #[error("WS Error: {0}")]
     WSError(#[from] WSError),
 
+    #[error("Invalid Gateway URL: {0}")]
+    InvalidUrl(#[from] crate::driver::UrlError),
+
     #[error("Gateway Disconnected")]
     Disconnected,
 
     #[error("Exceeded Reconnect Limit of {0} Attempts")]
     ReconnectLimitExceeded(usize),
 
+    #[error("IO Error: {0}")]
+    IOError(#[from] std::io::Error),
+
     #[error("Json Error: {0}")]
     JsonError(#[from] serde_json::Error),
 
@@ -21,9 +27,15 @@ pub enum GatewayError {
     #[error("CBOR Encode Error: {0}")]
     CborDecodeError(#[from] ciborium::de::Error<std::io::Error>),
 
+    #[error("Heartbeat Timeout")]
+    HeartbeatTimeout,
+
     #[error("Compression Error")]
     CompressionError,
 
+    #[error("Unknown Opcode: {0}")]
+    UnknownOpcode(u8),
+
     #[error("Close Error: {0:?}")]
     CloseError(GatewayErrorCode),
 }
@@ -0,0 +1,90 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::time::{Instant, Sleep};
+
+/// Action to take when the heartbeat timer fires
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum HeartbeatTick {
+    /// Send a heartbeat and wait for the ack
+    Send,
+
+    /// The last heartbeat was never acknowledged, so the connection is presumed dead
+    Timeout,
+}
+
+/// Heartbeat timer for a single connection, started by [`Hello`](crate::models::gateway::message::ServerMsg::Hello)
+pub(crate) struct Heartbeat {
+    interval: Duration,
+    sleep: Pin<Box<Sleep>>,
+    sent_at: Option<Instant>,
+}
+
+impl Heartbeat {
+    pub fn new(interval: Duration) -> Self {
+        Heartbeat {
+            interval,
+            sleep: Box::pin(tokio::time::sleep(interval)),
+            sent_at: None,
+        }
+    }
+
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<HeartbeatTick> {
+        futures::ready!(self.sleep.as_mut().poll(cx));
+
+        let now = Instant::now();
+
+        // wait up to another interval for the ack
+        self.sleep.as_mut().reset(now + self.interval);
+
+        Poll::Ready(match self.sent_at {
+            Some(_) => HeartbeatTick::Timeout,
+            None => {
+                self.sent_at = Some(now);
+                HeartbeatTick::Send
+            }
+        })
+    }
+
+    /// Returns the round-trip latency of the acknowledged heartbeat, if one was sent
+    pub fn on_ack(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+
+        self.sleep.as_mut().reset(now + self.interval);
+        self.sent_at.take().map(|sent_at| now - sent_at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::task::noop_waker_ref;
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_heartbeat() {
+        let mut cx = Context::from_waker(noop_waker_ref());
+
+        let interval = Duration::from_secs(45);
+        let mut hb = Heartbeat::new(interval);
+
+        assert!(hb.poll_tick(&mut cx).is_pending());
+
+        tokio::time::advance(interval).await;
+        assert_eq!(hb.poll_tick(&mut cx), Poll::Ready(HeartbeatTick::Send));
+        assert!(hb.poll_tick(&mut cx).is_pending());
+
+        tokio::time::advance(Duration::from_millis(150)).await;
+        assert_eq!(hb.on_ack(), Some(Duration::from_millis(150)));
+        assert_eq!(hb.on_ack(), None);
+
+        tokio::time::advance(interval).await;
+        assert_eq!(hb.poll_tick(&mut cx), Poll::Ready(HeartbeatTick::Send));
+
+        // no ack
+        tokio::time::advance(interval).await;
+        assert_eq!(hb.poll_tick(&mut cx), Poll::Ready(HeartbeatTick::Timeout));
+    }
+}
@@ -0,0 +1,421 @@
+//! Local websocket server speaking the gateway protocol, for testing code built on the
+//! [`GatewayConnection`](super::GatewayConnection) or [`Standard`](crate::framework::standard::Standard) framework
+//! without a real server.
+//!
+//! The server sends [Hello](ServerMsg::Hello) on connect, answers [Identify](ClientMsg::Identify) with
+//! [Ready](ServerMsg::Ready), accepts [Resume](ClientMsg::Resume) for sessions it has issued and
+//! acknowledges heartbeats. Everything else is up to the test.
+//!
+//! ```ignore
+//! let mut mock = MockGateway::bind().await?;
+//!
+//! let client = Client::new(&mock.uri())?;
+//! let mut gateway = GatewayConnection::new(client);
+//!
+//! let hello = gateway.next().await;
+//! gateway.send(ClientMsg::new_identify(identify)).await?;
+//!
+//! assert!(matches!(mock.recv().await, Some(ClientMsg::Identify(_))));
+//!
+//! mock.send(ServerMsg::new_relation_remove(user_id));
+//! mock.close(Some(GatewayErrorCode::UnknownError));
+//! ```
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use futures::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+use crate::api::gateway::GatewayQueryParams;
+use crate::driver::Encoding;
+use crate::models::{
+    events::{Hello, Ready},
+    gateway::message::{ClientMsg, ServerMsg},
+    Snowflake, User, UserFlags,
+};
+
+use super::codec::{Deflater, Inflater};
+use super::GatewayErrorCode;
+
+enum Command {
+    Send(ServerMsg),
+    Close(Option<GatewayErrorCode>),
+}
+
+struct MockShared {
+    heartbeat_interval: AtomicU32,
+    ack_heartbeats: AtomicBool,
+    user: Mutex<User>,
+    sessions: Mutex<Vec<Snowflake>>,
+    next_session: AtomicU64,
+    connections: AtomicUsize,
+    conns: Mutex<Vec<UnboundedSender<Arc<Command>>>>,
+    received: UnboundedSender<ClientMsg>,
+}
+
+/// Mock gateway server, listening on a local port until dropped
+pub struct MockGateway {
+    addr: SocketAddr,
+    shared: Arc<MockShared>,
+    received: UnboundedReceiver<ClientMsg>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for MockGateway {
+    fn drop(&mut self) {
+        self.task.abort();
+        self.close(None);
+    }
+}
+
+impl MockGateway {
+    /// Starts a mock gateway server on a random local port
+    pub async fn bind() -> std::io::Result<MockGateway> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        let addr = listener.local_addr()?;
+
+        let (tx, received) = mpsc::unbounded_channel();
+
+        let shared = Arc::new(MockShared {
+            heartbeat_interval: AtomicU32::new(Hello::default().heartbeat_interval),
+            ack_heartbeats: AtomicBool::new(true),
+            user: Mutex::new(User {
+                id: "1".parse().expect("valid snowflake"),
+                username: "mock".into(),
+                discriminator: 0,
+                flags: UserFlags::empty(),
+                profile: Default::default(),
+                email: None,
+                preferences: None,
+                presence: None,
+            }),
+            sessions: Mutex::default(),
+            next_session: AtomicU64::new(1),
+            connections: AtomicUsize::new(0),
+            conns: Mutex::default(),
+            received: tx,
+        });
+
+        let task = tokio::spawn({
+            let shared = shared.clone();
+
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(shared.clone().serve(stream));
+                }
+            }
+        });
+
+        Ok(MockGateway {
+            addr,
+            shared,
+            received,
+            task,
+        })
+    }
+
+    /// Base URL to pass to the [`Client`](crate::client::Client)
+    pub fn uri(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Heartbeat interval sent in [Hello](ServerMsg::Hello) to new connections, in milliseconds
+    pub fn set_heartbeat_interval(&self, ms: u32) {
+        self.shared.heartbeat_interval.store(ms, Ordering::SeqCst);
+    }
+
+    /// Whether to acknowledge heartbeats, defaults to true
+    pub fn set_ack_heartbeats(&self, ack: bool) {
+        self.shared.ack_heartbeats.store(ack, Ordering::SeqCst);
+    }
+
+    /// User sent in [Ready](ServerMsg::Ready)
+    pub fn set_user(&self, user: User) {
+        *self.shared.user.lock().unwrap_or_else(|e| e.into_inner()) = user;
+    }
+
+    /// Forget all sessions, so any [Resume](ClientMsg::Resume) is answered with [InvalidSession](ServerMsg::InvalidSession)
+    pub fn invalidate_sessions(&self) {
+        self.shared.sessions.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    /// Number of websocket connections accepted so far
+    pub fn connections(&self) -> usize {
+        self.shared.connections.load(Ordering::SeqCst)
+    }
+
+    /// Sends a message to all open connections
+    pub fn send(&self, msg: ServerMsg) {
+        self.shared.broadcast(Command::Send(msg));
+    }
+
+    /// Closes all open connections, with the given close code or a normal closure if `None`
+    pub fn close(&self, code: Option<GatewayErrorCode>) {
+        self.shared.broadcast(Command::Close(code));
+    }
+
+    /// Waits for the next message sent by any client, including those handled by the server itself
+    pub async fn recv(&mut self) -> Option<ClientMsg> {
+        self.received.recv().await
+    }
+}
+
+impl MockShared {
+    fn broadcast(&self, cmd: Command) {
+        let cmd = Arc::new(cmd);
+        let mut conns = self.conns.lock().unwrap_or_else(|e| e.into_inner());
+
+        conns.retain(|conn| conn.send(cmd.clone()).is_ok());
+    }
+
+    // the handshake callback's error type is given by tungstenite
+    #[allow(clippy::result_large_err)]
+    async fn serve(self: Arc<Self>, stream: TcpStream) {
+        let mut params = GatewayQueryParams::default();
+
+        let ws = tokio_tungstenite::accept_hdr_async(stream, |req: &Request, res: Response| {
+            params = serde_urlencoded::from_str(req.uri().query().unwrap_or_default()).unwrap_or_default();
+            Ok(res)
+        })
+        .await;
+
+        let ws = match ws {
+            Ok(ws) => ws,
+            Err(_) => return,
+        };
+
+        self.connections.fetch_add(1, Ordering::SeqCst);
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        self.conns.lock().unwrap_or_else(|e| e.into_inner()).push(tx.clone());
+
+        let (mut sink, mut stream) = ws.split();
+
+        // writer, owning the compression context for this connection
+        tokio::spawn(async move {
+            let mut deflater = params.compress.then(|| Deflater::new(9, params.stream));
+
+            while let Some(cmd) = rx.recv().await {
+                let res = match *cmd {
+                    Command::Send(ref msg) => {
+                        let mut body = Vec::new();
+
+                        match params.encoding {
+                            Encoding::JSON => serde_json::to_writer(&mut body, msg).expect("unable to encode message"),
+                            #[cfg(feature = "cbor")]
+                            Encoding::CBOR => ciborium::ser::into_writer(msg, &mut body).expect("unable to encode message"),
+                        }
+
+                        if let Some(ref mut deflater) = deflater {
+                            let mut compressed = Vec::new();
+                            assert!(deflater.compress(&body, &mut compressed), "unable to compress message");
+                            body = compressed;
+                        }
+
+                        sink.send(WsMessage::Binary(body)).await
+                    }
+                    Command::Close(code) => {
+                        let frame = CloseFrame {
+                            code: match code {
+                                Some(code) => CloseCode::from(code as u16),
+                                None => CloseCode::Normal,
+                            },
+                            reason: "".into(),
+                        };
+
+                        let _ = sink.send(WsMessage::Close(Some(frame))).await;
+                        break;
+                    }
+                };
+
+                if res.is_err() {
+                    break;
+                }
+            }
+        });
+
+        self.send_to(
+            &tx,
+            ServerMsg::new_hello(Hello {
+                heartbeat_interval: self.heartbeat_interval.load(Ordering::SeqCst),
+            }),
+        );
+
+        let mut inflater = params.compress.then(|| Inflater::new(params.stream));
+        let mut buffer = Vec::new();
+
+        while let Some(Ok(msg)) = stream.next().await {
+            if msg.is_close() {
+                break;
+            }
+
+            let data = msg.into_data();
+
+            let body = match inflater {
+                Some(ref mut inflater) => match inflater.decompress(&data, &mut buffer) {
+                    true => &buffer,
+                    false => break,
+                },
+                None => &data,
+            };
+
+            let msg: ClientMsg = match params.encoding {
+                Encoding::JSON => match serde_json::from_slice(body) {
+                    Ok(msg) => msg,
+                    Err(_) => break,
+                },
+                #[cfg(feature = "cbor")]
+                Encoding::CBOR => match ciborium::de::from_reader(&body[..]) {
+                    Ok(msg) => msg,
+                    Err(_) => break,
+                },
+            };
+
+            self.respond(&tx, &msg);
+
+            let _ = self.received.send(msg);
+        }
+
+        // stop the writer, with a decode error if the loop ended early
+        let _ = tx.send(Arc::new(Command::Close(Some(GatewayErrorCode::DecodeError))));
+    }
+
+    fn send_to(&self, tx: &UnboundedSender<Arc<Command>>, msg: ServerMsg) {
+        let _ = tx.send(Arc::new(Command::Send(msg)));
+    }
+
+    fn respond(&self, tx: &UnboundedSender<Arc<Command>>, msg: &ClientMsg) {
+        match msg {
+            ClientMsg::Heartbeat(_) if self.ack_heartbeats.load(Ordering::SeqCst) => {
+                self.send_to(tx, ServerMsg::new_heartbeat_ack());
+            }
+            ClientMsg::Identify(_) => {
+                let id = self.next_session.fetch_add(1, Ordering::SeqCst);
+                let session: Snowflake = id.to_string().parse().expect("valid snowflake");
+
+                self.sessions.lock().unwrap_or_else(|e| e.into_inner()).push(session);
+
+                let ready = Ready {
+                    user: self.user.lock().unwrap_or_else(|e| e.into_inner()).clone(),
+                    dms: Default::default(),
+                    parties: Default::default(),
+                    session,
+                };
+
+                self.send_to(tx, ServerMsg::new_ready(ready));
+            }
+            ClientMsg::Resume(resume) => {
+                let valid = self.sessions.lock().unwrap_or_else(|e| e.into_inner()).contains(&resume.session);
+
+                // a valid resume is accepted silently, and confirmed by the next event
+                if !valid {
+                    self.send_to(tx, ServerMsg::new_invalid_session());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::{SinkExt, StreamExt};
+
+    use super::*;
+    use crate::client::Client;
+    use crate::gateway::{GatewayConfig, GatewayConnection, GatewayError, ReconnectBackoff, SessionStart};
+    use crate::models::{commands::Identify, Intent};
+
+    #[tokio::test]
+    async fn test_mock_gateway() {
+        let mut mock = MockGateway::bind().await.unwrap();
+
+        let client = Client::new(&mock.uri()).unwrap();
+
+        let config = GatewayConfig {
+            stream: true,
+            ..GatewayConfig::DEFAULT
+        };
+
+        let mut gateway = GatewayConnection::with_config(client, config);
+        let control = gateway.control();
+
+        control.set_backoff(ReconnectBackoff {
+            base_delay: Duration::from_millis(1),
+            ..ReconnectBackoff::DEFAULT
+        });
+
+        assert!(matches!(gateway.next().await, Some(Ok(ServerMsg::Hello(_)))));
+
+        let identify = Identify {
+            auth: "a".repeat(28).parse().unwrap(),
+            intent: Intent::all(),
+        };
+
+        gateway.send(ClientMsg::new_identify(identify)).await.unwrap();
+
+        assert!(matches!(mock.recv().await, Some(ClientMsg::Identify(_))));
+        assert!(matches!(gateway.next().await, Some(Ok(ServerMsg::Ready(_)))));
+        assert_eq!(control.session_start(), Some(SessionStart::Identified));
+
+        mock.close(Some(GatewayErrorCode::UnknownOpcode));
+
+        assert!(matches!(
+            gateway.next().await,
+            Some(Err(GatewayError::CloseError(GatewayErrorCode::UnknownOpcode)))
+        ));
+
+        // reconnects and starts a new session, as the policy discards the session after protocol errors
+        assert!(matches!(gateway.next().await, Some(Ok(ServerMsg::Hello(_)))));
+        assert!(!control.is_resuming());
+
+        gateway
+            .send(ClientMsg::new_identify(Identify {
+                auth: "a".repeat(28).parse().unwrap(),
+                intent: Intent::all(),
+            }))
+            .await
+            .unwrap();
+
+        assert!(matches!(gateway.next().await, Some(Ok(ServerMsg::Ready(_)))));
+
+        // resumes after a normal disconnect
+        mock.close(None);
+
+        assert!(matches!(gateway.next().await, Some(Err(GatewayError::Disconnected))));
+        assert!(matches!(gateway.next().await, Some(Ok(ServerMsg::Hello(_)))));
+        assert!(control.is_resuming());
+
+        mock.send(ServerMsg::new_relation_remove("2".parse::<Snowflake>().unwrap()));
+
+        assert!(matches!(gateway.next().await, Some(Ok(ServerMsg::RelationRemove(_)))));
+        assert_eq!(control.session_start(), Some(SessionStart::Resumed));
+        assert_eq!(mock.connections(), 3);
+        assert_eq!(control.stats().snapshot().resumes, 1);
+
+        // resumes again, with an ack arriving before the event confirming the resume
+        mock.close(None);
+
+        assert!(matches!(gateway.next().await, Some(Err(GatewayError::Disconnected))));
+        assert!(matches!(gateway.next().await, Some(Ok(ServerMsg::Hello(_)))));
+        assert!(control.is_resuming());
+
+        mock.send(ServerMsg::new_heartbeat_ack());
+        assert!(matches!(gateway.next().await, Some(Ok(ServerMsg::HeartbeatAck(_)))));
+
+        mock.send(ServerMsg::new_relation_remove("2".parse::<Snowflake>().unwrap()));
+        assert!(matches!(gateway.next().await, Some(Ok(ServerMsg::RelationRemove(_)))));
+
+        let stats = control.stats().snapshot();
+        assert_eq!((stats.identifies, stats.resumes), (2, 2));
+    }
+}
@@ -1,7 +1,26 @@
+mod backoff;
+mod codec;
+mod config;
 mod conn;
 mod error;
+mod heartbeat;
+mod policy;
+mod record;
+mod router;
+mod session;
 mod socket;
+mod stats;
 
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+
+pub use backoff::{CircuitState, ReconnectBackoff};
+pub use config::GatewayConfig;
 pub use conn::{GatewayConnection, GatewayConnectionControl};
 pub use error::{GatewayError, GatewayErrorCode};
+pub use policy::{DefaultReconnectPolicy, ReconnectAction, ReconnectPolicy};
+pub use record::{CaptureMsg, CaptureRecord, GatewayRecorder, GatewayReplay};
+pub use router::{EventFilter, GatewayRouter, GatewaySubscription, Overflow};
+pub use session::SessionStart;
 pub use socket::GatewaySocket;
+pub use stats::{GatewayStats, GatewayStatsSnapshot, LATENCY_HISTORY_LEN};
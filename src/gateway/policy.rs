@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use tokio_tungstenite::tungstenite::{http::StatusCode, Error as WSError};
+
+use super::{GatewayError, GatewayErrorCode};
+
+/// What a [`GatewayConnection`](super::GatewayConnection) should do after an error
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReconnectAction {
+    /// Reconnect and resume the current session, if any
+    Resume,
+
+    /// Reconnect and start a new session, discarding the current one
+    Identify,
+
+    /// Reconnect and resume after waiting at least the given duration, in addition to the regular backoff
+    Backoff(Duration),
+
+    /// Stop reconnecting, until [`reset`](super::GatewayConnectionControl::reset)
+    Stop,
+}
+
+/// Decides how to reconnect after each error that caused the gateway connection to be dropped,
+/// see [`GatewayConnectionControl::set_reconnect_policy`](super::GatewayConnectionControl::set_reconnect_policy).
+///
+/// Implemented for closures, and [`DefaultReconnectPolicy`] by default.
+pub trait ReconnectPolicy: Send + Sync + 'static {
+    fn on_error(&self, error: &GatewayError) -> ReconnectAction;
+}
+
+impl<F> ReconnectPolicy for F
+where
+    F: Fn(&GatewayError) -> ReconnectAction + Send + Sync + 'static,
+{
+    #[inline]
+    fn on_error(&self, error: &GatewayError) -> ReconnectAction {
+        self(error)
+    }
+}
+
+/// Default [`ReconnectPolicy`]
+///
+/// * Stops after authentication failures or with an invalid gateway URL, as retrying would not help.
+/// * Starts a new session after protocol errors, as the current session may be in an unknown state.
+/// * Backs off when rate-limited while connecting, or after unknown server errors.
+/// * Otherwise resumes, such as after network errors.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct DefaultReconnectPolicy;
+
+impl DefaultReconnectPolicy {
+    /// Extra delay after being rate-limited or unknown server errors
+    pub const BACKOFF: Duration = Duration::from_secs(30);
+}
+
+impl ReconnectPolicy for DefaultReconnectPolicy {
+    fn on_error(&self, error: &GatewayError) -> ReconnectAction {
+        match error {
+            GatewayError::CloseError(code) => match code {
+                GatewayErrorCode::AuthFailed | GatewayErrorCode::NotAuthenticated => ReconnectAction::Stop,
+                GatewayErrorCode::DecodeError | GatewayErrorCode::UnknownOpcode => ReconnectAction::Identify,
+                GatewayErrorCode::UnknownError => ReconnectAction::Backoff(Self::BACKOFF),
+            },
+            GatewayError::WSError(WSError::Http(response)) => match response.status() {
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => ReconnectAction::Stop,
+                StatusCode::TOO_MANY_REQUESTS => ReconnectAction::Backoff(Self::BACKOFF),
+                status if status.is_server_error() => ReconnectAction::Backoff(Self::BACKOFF),
+                _ => ReconnectAction::Resume,
+            },
+            GatewayError::JsonError(_) | GatewayError::CompressionError | GatewayError::UnknownOpcode(_) => {
+                ReconnectAction::Identify
+            }
+            #[cfg(feature = "cbor")]
+            GatewayError::CborEncodeError(_) | GatewayError::CborDecodeError(_) => ReconnectAction::Identify,
+            GatewayError::ReconnectLimitExceeded(_) | GatewayError::InvalidUrl(_) => ReconnectAction::Stop,
+            _ => ReconnectAction::Resume,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_reconnect_policy() {
+        let policy = DefaultReconnectPolicy;
+
+        assert_eq!(
+            policy.on_error(&GatewayError::CloseError(GatewayErrorCode::AuthFailed)),
+            ReconnectAction::Stop
+        );
+        assert_eq!(
+            policy.on_error(&GatewayError::CloseError(GatewayErrorCode::DecodeError)),
+            ReconnectAction::Identify
+        );
+        assert_eq!(policy.on_error(&GatewayError::Disconnected), ReconnectAction::Resume);
+
+        let mut response = tokio_tungstenite::tungstenite::http::Response::new(None);
+        *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+
+        assert_eq!(
+            policy.on_error(&GatewayError::WSError(WSError::Http(response))),
+            ReconnectAction::Backoff(DefaultReconnectPolicy::BACKOFF)
+        );
+    }
+}
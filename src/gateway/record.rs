@@ -0,0 +1,549 @@
+//! Capture of gateway traffic for debugging, and replay of captures into handlers.
+//!
+//! Captures are a sequence of [`CaptureRecord`]s, encoded either as newline-delimited JSON,
+//! or as a CBOR sequence where each record directly follows the previous one.
+//!
+//! ```ignore
+//! let (recorder, writer) = GatewayRecorder::create("capture.jsonl", Encoding::JSON).await?;
+//! tokio::spawn(writer);
+//! gateway.control().set_recorder(Some(Arc::new(recorder)));
+//!
+//! // later, feed the capture back in at 10x speed
+//! let replay = GatewayReplay::open("capture.jsonl", Encoding::JSON).await?.with_speed(10.0);
+//! ```
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::{channel::mpsc, FutureExt, Stream, StreamExt};
+use serde::de::{self, Deserialize, Deserializer, MapAccess, Visitor};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use tokio::io::{AsyncBufRead, AsyncWrite, AsyncWriteExt};
+use tokio::time::{Instant, Sleep};
+
+use crate::driver::Encoding;
+use crate::models::{
+    gateway::message::{ClientMsg, ServerMsg},
+    Timestamp,
+};
+
+use super::GatewayError;
+
+/// Message captured in either direction
+#[derive(Debug)]
+pub enum CaptureMsg {
+    Sent(ClientMsg),
+    Received(ServerMsg),
+}
+
+/// Single message in a capture
+#[derive(Debug)]
+pub struct CaptureRecord {
+    /// Time since the recording started
+    pub elapsed: Duration,
+
+    /// Wall-clock time the message was captured
+    pub timestamp: Timestamp,
+
+    pub msg: CaptureMsg,
+}
+
+#[derive(Clone, Copy)]
+enum CaptureMsgRef<'a> {
+    Sent(&'a ClientMsg),
+    Received(&'a ServerMsg),
+}
+
+#[derive(Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Direction {
+    Sent,
+    Received,
+}
+
+struct CaptureRecordRef<'a> {
+    elapsed: Duration,
+    timestamp: Timestamp,
+    msg: CaptureMsgRef<'a>,
+}
+
+impl Serialize for CaptureRecordRef<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("CaptureRecord", 4)?;
+
+        state.serialize_field("t", &u64::try_from(self.elapsed.as_micros()).unwrap_or(u64::MAX))?;
+        state.serialize_field("ts", &self.timestamp)?;
+
+        match self.msg {
+            CaptureMsgRef::Sent(msg) => {
+                state.serialize_field("dir", &Direction::Sent)?;
+                state.serialize_field("msg", msg)?;
+            }
+            CaptureMsgRef::Received(msg) => {
+                state.serialize_field("dir", &Direction::Received)?;
+                state.serialize_field("msg", msg)?;
+            }
+        }
+
+        state.end()
+    }
+}
+
+impl Serialize for CaptureRecord {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        CaptureRecordRef {
+            elapsed: self.elapsed,
+            timestamp: self.timestamp,
+            msg: match self.msg {
+                CaptureMsg::Sent(ref msg) => CaptureMsgRef::Sent(msg),
+                CaptureMsg::Received(ref msg) => CaptureMsgRef::Received(msg),
+            },
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for CaptureRecord {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Clone, Copy, serde::Deserialize)]
+        enum Field {
+            #[serde(rename = "t")]
+            Elapsed,
+
+            #[serde(rename = "ts")]
+            Timestamp,
+
+            #[serde(rename = "dir")]
+            Direction,
+
+            #[serde(rename = "msg")]
+            Message,
+        }
+
+        struct RecordVisitor;
+
+        impl<'de> Visitor<'de> for RecordVisitor {
+            type Value = CaptureRecord;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("struct CaptureRecord")
+            }
+
+            fn visit_map<V>(self, mut map: V) -> Result<Self::Value, V::Error>
+            where
+                V: MapAccess<'de>,
+            {
+                let mut elapsed = None;
+                let mut timestamp = None;
+                let mut dir = None;
+                let mut msg = None;
+
+                while let Some(key) = map.next_key()? {
+                    match key {
+                        Field::Elapsed => elapsed = Some(Duration::from_micros(map.next_value()?)),
+                        Field::Timestamp => timestamp = Some(map.next_value()?),
+                        Field::Direction => dir = Some(map.next_value()?),
+                        Field::Message => {
+                            msg = Some(match dir {
+                                Some(Direction::Sent) => CaptureMsg::Sent(map.next_value()?),
+                                Some(Direction::Received) => CaptureMsg::Received(map.next_value()?),
+                                None => return Err(de::Error::custom("Missing direction before message")),
+                            })
+                        }
+                    }
+                }
+
+                Ok(CaptureRecord {
+                    elapsed: elapsed.ok_or_else(|| de::Error::missing_field("t"))?,
+                    timestamp: timestamp.ok_or_else(|| de::Error::missing_field("ts"))?,
+                    msg: msg.ok_or_else(|| de::Error::missing_field("msg"))?,
+                })
+            }
+        }
+
+        deserializer.deserialize_struct("CaptureRecord", &["t", "ts", "dir", "msg"], RecordVisitor)
+    }
+}
+
+/// Writes every message sent or received by a [`GatewayConnection`](super::GatewayConnection) to a capture,
+/// see [`GatewayConnectionControl::set_recorder`](super::GatewayConnectionControl::set_recorder).
+///
+/// Records are only encoded by the connection, and written by a separate writer task,
+/// so a slow writer never holds up the connection.
+pub struct GatewayRecorder {
+    start: Instant,
+    encoding: Encoding,
+    tx: mpsc::UnboundedSender<io::Result<Vec<u8>>>,
+}
+
+impl GatewayRecorder {
+    /// Records to the given writer, returning the writer task that must be spawned for the capture to be written.
+    ///
+    /// The task completes once the recorder is dropped and all records are written and flushed,
+    /// or with the first error, after which recording stops so the capture isn't left with gaps.
+    pub fn new<W>(writer: W, encoding: Encoding) -> (Self, impl Future<Output = io::Result<()>> + Send + 'static)
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        let (tx, rx) = mpsc::unbounded();
+
+        let recorder = GatewayRecorder {
+            start: Instant::now(),
+            encoding,
+            tx,
+        };
+
+        (recorder, write_records(writer, rx))
+    }
+
+    /// Creates or truncates the file at the given path and records to it, see [`new`](GatewayRecorder::new)
+    #[cfg(feature = "fs")]
+    pub async fn create(
+        path: impl AsRef<Path>,
+        encoding: Encoding,
+    ) -> io::Result<(Self, impl Future<Output = io::Result<()>> + Send + 'static)> {
+        let file = tokio::fs::File::create(path).await?;
+
+        Ok(Self::new(tokio::io::BufWriter::new(file), encoding))
+    }
+
+    pub(crate) fn record_sent(&self, msg: &ClientMsg) {
+        self.record(CaptureMsgRef::Sent(msg));
+    }
+
+    pub(crate) fn record_received(&self, msg: &ServerMsg) {
+        self.record(CaptureMsgRef::Received(msg));
+    }
+
+    fn record(&self, msg: CaptureMsgRef) {
+        // the writer task has stopped
+        if self.tx.is_closed() {
+            return;
+        }
+
+        let record = CaptureRecordRef {
+            elapsed: self.start.elapsed(),
+            timestamp: Timestamp::now_utc(),
+            msg,
+        };
+
+        let mut buf = Vec::new();
+
+        let res = match self.encoding {
+            Encoding::JSON => serde_json::to_writer(&mut buf, &record).map(|_| buf.push(b'\n')).map_err(io::Error::from),
+            #[cfg(feature = "cbor")]
+            Encoding::CBOR => ciborium::ser::into_writer(&record, &mut buf).map_err(|e| match e {
+                ciborium::ser::Error::Io(e) => e,
+                ciborium::ser::Error::Value(e) => io::Error::new(io::ErrorKind::InvalidData, e),
+            }),
+        };
+
+        let _ = self.tx.unbounded_send(res.map(|_| buf));
+    }
+}
+
+/// Writer task of a [`GatewayRecorder`]
+async fn write_records<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    mut rx: mpsc::UnboundedReceiver<io::Result<Vec<u8>>>,
+) -> io::Result<()> {
+    loop {
+        let record = match rx.next().now_or_never() {
+            Some(Some(record)) => record,
+            Some(None) => break,
+            // flush while waiting on more, so the capture is complete up to the latest message
+            None => {
+                writer.flush().await?;
+
+                match rx.next().await {
+                    Some(record) => record,
+                    None => break,
+                }
+            }
+        };
+
+        writer.write_all(&record?).await?;
+    }
+
+    writer.flush().await
+}
+
+/// Replays the received messages of a capture as a [`Stream`], like a [`GatewayConnection`](super::GatewayConnection),
+/// at the original pace or faster.
+pub struct GatewayReplay<R> {
+    reader: R,
+    encoding: Encoding,
+    speed: f64,
+    /// When the replay started, and the scaled capture time of the first message
+    start: Option<(Instant, Duration)>,
+    sleep: Option<Pin<Box<Sleep>>>,
+    /// Bytes read from the capture, but not yet decoded
+    buffer: Vec<u8>,
+    eof: bool,
+    pending: Option<ServerMsg>,
+}
+
+#[cfg(feature = "fs")]
+impl GatewayReplay<tokio::io::BufReader<tokio::fs::File>> {
+    /// Opens the capture file at the given path
+    pub async fn open(path: impl AsRef<Path>, encoding: Encoding) -> io::Result<Self> {
+        Ok(Self::new(
+            tokio::io::BufReader::new(tokio::fs::File::open(path).await?),
+            encoding,
+        ))
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> GatewayReplay<R> {
+    pub fn new(reader: R, encoding: Encoding) -> Self {
+        GatewayReplay {
+            reader,
+            encoding,
+            speed: 1.0,
+            start: None,
+            sleep: None,
+            buffer: Vec::new(),
+            eof: false,
+            pending: None,
+        }
+    }
+
+    /// Sets the replay speed relative to the original, e.g. `2.0` for twice as fast.
+    ///
+    /// Non-positive or infinite speeds replay messages as fast as they are polled.
+    pub fn with_speed(mut self, speed: f64) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Reads the next record from the capture, or `None` at the end
+    pub async fn next_record(&mut self) -> Option<Result<CaptureRecord, GatewayError>> {
+        std::future::poll_fn(|cx| self.poll_next_record(cx)).await
+    }
+
+    fn poll_next_record(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<CaptureRecord, GatewayError>>> {
+        loop {
+            if let Some(res) = self.decode_buffered() {
+                return Poll::Ready(Some(res));
+            }
+
+            if self.eof {
+                return Poll::Ready(None);
+            }
+
+            let available = match futures::ready!(Pin::new(&mut self.reader).poll_fill_buf(cx)) {
+                Ok(available) => available,
+                Err(e) => return Poll::Ready(Some(Err(e.into()))),
+            };
+
+            let len = available.len();
+
+            self.eof = len == 0;
+            self.buffer.extend_from_slice(available);
+
+            Pin::new(&mut self.reader).consume(len);
+        }
+    }
+
+    /// Decodes the next complete record in the buffer, if any
+    fn decode_buffered(&mut self) -> Option<Result<CaptureRecord, GatewayError>> {
+        match self.encoding {
+            Encoding::JSON => loop {
+                let end = match self.buffer.iter().position(|&b| b == b'\n') {
+                    Some(pos) => pos + 1,
+                    // the last line may not end with a newline
+                    None if self.eof && !self.buffer.is_empty() => self.buffer.len(),
+                    None => return None,
+                };
+
+                let line: Vec<u8> = self.buffer.drain(..end).collect();
+
+                // skip blank lines
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+
+                return Some(serde_json::from_slice(&line).map_err(GatewayError::from));
+            },
+            #[cfg(feature = "cbor")]
+            Encoding::CBOR => {
+                if self.buffer.is_empty() {
+                    return None;
+                }
+
+                let mut remaining = &self.buffer[..];
+
+                match ciborium::de::from_reader(&mut remaining) {
+                    Ok(record) => {
+                        let consumed = self.buffer.len() - remaining.len();
+                        self.buffer.drain(..consumed);
+
+                        Some(Ok(record))
+                    }
+                    // incomplete record, so wait on more unless there is none
+                    Err(ciborium::de::Error::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof && !self.eof => None,
+                    Err(e) => {
+                        self.buffer.clear();
+
+                        Some(Err(e.into()))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Scales the capture time by the replay speed, or `None` if not paced
+    fn scale(&self, elapsed: Duration) -> Option<Duration> {
+        if !self.speed.is_finite() || self.speed <= 0.0 {
+            return None;
+        }
+
+        Some(elapsed.div_f64(self.speed))
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> Stream for GatewayReplay<R> {
+    type Item = Result<ServerMsg, GatewayError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        loop {
+            if let Some(ref mut sleep) = this.sleep {
+                futures::ready!(sleep.as_mut().poll(cx));
+                this.sleep = None;
+            }
+
+            if let Some(msg) = this.pending.take() {
+                return Poll::Ready(Some(Ok(msg)));
+            }
+
+            let record = match futures::ready!(this.poll_next_record(cx)) {
+                None => return Poll::Ready(None),
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                Some(Ok(record)) => record,
+            };
+
+            // sent messages only serve to keep the timing
+            let msg = match record.msg {
+                CaptureMsg::Received(msg) => msg,
+                CaptureMsg::Sent(_) => continue,
+            };
+
+            let offset = match this.scale(record.elapsed) {
+                Some(offset) => offset,
+                None => return Poll::Ready(Some(Ok(msg))),
+            };
+
+            // the replay starts with the first message, no matter when it was captured
+            let (started, first) = *this.start.get_or_insert((Instant::now(), offset));
+            let deadline = started + offset.saturating_sub(first);
+
+            if deadline <= Instant::now() {
+                return Poll::Ready(Some(Ok(msg)));
+            }
+
+            this.sleep = Some(Box::pin(tokio::time::sleep_until(deadline)));
+            this.pending = Some(msg);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    /// Records a short session, returning the capture
+    async fn capture(encoding: Encoding) -> Vec<u8> {
+        let (writer, mut reader) = tokio::io::duplex(64);
+
+        let (recorder, task) = GatewayRecorder::new(writer, encoding);
+        let task = tokio::spawn(task);
+
+        recorder.record_received(&ServerMsg::new_hello(crate::models::events::Hello::default()));
+        recorder.record_sent(&ClientMsg::new_heartbeat());
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        recorder.record_received(&ServerMsg::new_heartbeat_ack());
+
+        drop(recorder);
+
+        // read concurrently, as the pipe is smaller than the capture
+        let mut capture = Vec::new();
+        reader.read_to_end(&mut capture).await.unwrap();
+
+        task.await.unwrap().unwrap();
+
+        capture
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_record_replay() {
+        let capture = capture(Encoding::JSON).await;
+        assert_eq!(capture.iter().filter(|&&b| b == b'\n').count(), 3);
+
+        let mut replay = GatewayReplay::new(&capture[..], Encoding::JSON).with_speed(2.0);
+
+        let start = Instant::now();
+
+        assert!(matches!(replay.next().await, Some(Ok(ServerMsg::Hello(_)))));
+        assert!(matches!(replay.next().await, Some(Ok(ServerMsg::HeartbeatAck(_)))));
+        assert!(replay.next().await.is_none());
+
+        // twice as fast
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+    }
+
+    #[cfg(feature = "cbor")]
+    #[tokio::test(start_paused = true)]
+    async fn test_replay_partial_reads() {
+        for encoding in [Encoding::JSON, Encoding::CBOR] {
+            let capture = capture(encoding).await;
+
+            // records span several reads
+            let reader = tokio::io::BufReader::with_capacity(7, &capture[..]);
+            let mut replay = GatewayReplay::new(reader, encoding).with_speed(0.0);
+
+            assert!(matches!(
+                replay.next_record().await,
+                Some(Ok(CaptureRecord {
+                    msg: CaptureMsg::Received(_),
+                    ..
+                }))
+            ));
+            assert!(matches!(
+                replay.next_record().await,
+                Some(Ok(CaptureRecord {
+                    msg: CaptureMsg::Sent(_),
+                    ..
+                }))
+            ));
+            assert!(matches!(replay.next().await, Some(Ok(ServerMsg::HeartbeatAck(_)))));
+            assert!(replay.next().await.is_none());
+
+            // truncated capture
+            let mut replay = GatewayReplay::new(&capture[..capture.len() - 2], encoding).with_speed(0.0);
+
+            assert!(matches!(replay.next().await, Some(Ok(ServerMsg::Hello(_)))));
+            assert!(matches!(replay.next().await, Some(Err(_))));
+            assert!(replay.next().await.is_none());
+        }
+    }
+}
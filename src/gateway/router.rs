@@ -0,0 +1,465 @@
+//! Client-side filtering of gateway events, fanning them out to multiple subscribers.
+//!
+//! Each [`GatewaySubscription`] has its own [`EventFilter`] and bounded queue, with an [`Overflow`]
+//! behavior for when the subscriber falls behind.
+//!
+//! ```ignore
+//! let router = GatewayRouter::new();
+//!
+//! let mut messages = router.subscribe(EventFilter::new(Intent::MESSAGES).room(room_id), 64, Overflow::DropOldest);
+//! let mut members = router.subscribe(EventFilter::new(Intent::PARTY_MEMBERS), 16, Overflow::Block);
+//!
+//! tokio::spawn(async move { router.run(&mut gateway).await });
+//!
+//! while let Some(event) = messages.next().await { /* ... */ }
+//! ```
+
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+
+use futures::{Stream, StreamExt};
+
+use crate::models::{gateway::message::ServerMsg, Intent, Snowflake};
+
+/// Behavior when an event is routed to a subscriber whose queue is full
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Drop the oldest queued event to make room for the new one
+    #[default]
+    DropOldest,
+
+    /// Wait for the subscriber to make room, which also holds back all other subscribers
+    Block,
+
+    /// Disconnect the subscriber, ending its stream after the queued events
+    Disconnect,
+}
+
+/// Selects which events are delivered to a subscriber
+///
+/// Events must match the intents, if they have any, and every filter that has been set.
+/// Events without a party, room or user are not delivered to subscribers filtering on them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventFilter {
+    pub intents: Intent,
+    pub parties: Option<Vec<Snowflake>>,
+    pub rooms: Option<Vec<Snowflake>>,
+    pub users: Option<Vec<Snowflake>>,
+}
+
+impl Default for EventFilter {
+    fn default() -> Self {
+        EventFilter::new(Intent::all())
+    }
+}
+
+impl EventFilter {
+    pub const fn new(intents: Intent) -> Self {
+        EventFilter {
+            intents,
+            parties: None,
+            rooms: None,
+            users: None,
+        }
+    }
+
+    /// Also accept events from the given party
+    pub fn party(mut self, party_id: Snowflake) -> Self {
+        self.parties.get_or_insert_with(Vec::new).push(party_id);
+        self
+    }
+
+    /// Also accept events from the given room
+    pub fn room(mut self, room_id: Snowflake) -> Self {
+        self.rooms.get_or_insert_with(Vec::new).push(room_id);
+        self
+    }
+
+    /// Also accept events from the given user
+    pub fn user(mut self, user_id: Snowflake) -> Self {
+        self.users.get_or_insert_with(Vec::new).push(user_id);
+        self
+    }
+
+    pub fn matches(&self, msg: &ServerMsg) -> bool {
+        fn matches_id(ids: &Option<Vec<Snowflake>>, id: Option<Snowflake>) -> bool {
+            match (ids, id) {
+                (None, _) => true,
+                (Some(ids), Some(id)) => ids.contains(&id),
+                (Some(_), None) => false,
+            }
+        }
+
+        if let Some(intent) = msg.matching_intent() {
+            if !self.intents.contains(intent) {
+                return false;
+            }
+        }
+
+        matches_id(&self.parties, msg.party_id())
+            && matches_id(&self.rooms, msg.room_id())
+            && matches_id(&self.users, msg.user_id())
+    }
+}
+
+struct Queue {
+    events: VecDeque<Arc<ServerMsg>>,
+    closed: bool,
+    dropped: u64,
+    rx_waker: Option<Waker>,
+
+    /// Routing tasks waiting for room in the queue
+    tx_wakers: Vec<Waker>,
+}
+
+impl Queue {
+    fn wake_senders(&mut self) {
+        for waker in self.tx_wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+struct Subscriber {
+    filter: EventFilter,
+    capacity: usize,
+    overflow: Overflow,
+    queue: Mutex<Queue>,
+}
+
+impl Subscriber {
+    fn queue(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn close(&self) {
+        let mut queue = self.queue();
+
+        queue.closed = true;
+
+        if let Some(waker) = queue.rx_waker.take() {
+            waker.wake();
+        }
+
+        queue.wake_senders();
+    }
+
+    /// Attempts to queue the event, returning it if the subscriber is full and blocking
+    fn try_push(&self, event: Arc<ServerMsg>, cx: Option<&mut Context>) -> Option<Arc<ServerMsg>> {
+        let mut queue = self.queue();
+
+        if queue.closed {
+            return None;
+        }
+
+        if queue.events.len() >= self.capacity {
+            match self.overflow {
+                Overflow::DropOldest => {
+                    queue.events.pop_front();
+                    queue.dropped += 1;
+                }
+                Overflow::Disconnect => {
+                    queue.dropped += 1;
+                    drop(queue);
+                    self.close();
+                    return None;
+                }
+                Overflow::Block => {
+                    if let Some(cx) = cx {
+                        if !queue.tx_wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                            queue.tx_wakers.push(cx.waker().clone());
+                        }
+                    }
+
+                    return Some(event);
+                }
+            }
+        }
+
+        queue.events.push_back(event);
+
+        if let Some(waker) = queue.rx_waker.take() {
+            waker.wake();
+        }
+
+        None
+    }
+}
+
+struct RouterInner {
+    subscribers: Mutex<Vec<Arc<Subscriber>>>,
+}
+
+impl Drop for RouterInner {
+    fn drop(&mut self) {
+        for subscriber in self.subscribers.get_mut().unwrap_or_else(|e| e.into_inner()).drain(..) {
+            subscriber.close();
+        }
+    }
+}
+
+/// Fans out gateway events to any number of [`GatewaySubscription`]s, according to their [`EventFilter`]s.
+///
+/// Cloning the router is cheap, and all clones share the same subscribers.
+/// Once all clones are dropped, subscriptions end after their queued events.
+#[derive(Clone)]
+pub struct GatewayRouter {
+    inner: Arc<RouterInner>,
+}
+
+impl Default for GatewayRouter {
+    fn default() -> Self {
+        GatewayRouter::new()
+    }
+}
+
+impl GatewayRouter {
+    pub fn new() -> Self {
+        GatewayRouter {
+            inner: Arc::new(RouterInner {
+                subscribers: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    fn subscribers(&self) -> MutexGuard<'_, Vec<Arc<Subscriber>>> {
+        self.inner.subscribers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Add a subscriber receiving events that match the filter, queueing up to `capacity` events.
+    pub fn subscribe(&self, filter: EventFilter, capacity: usize, overflow: Overflow) -> GatewaySubscription {
+        let capacity = capacity.max(1);
+
+        let subscriber = Arc::new(Subscriber {
+            filter,
+            capacity,
+            overflow,
+            queue: Mutex::new(Queue {
+                events: VecDeque::with_capacity(capacity.min(64)),
+                closed: false,
+                dropped: 0,
+                rx_waker: None,
+                tx_wakers: Vec::new(),
+            }),
+        });
+
+        self.subscribers().push(subscriber.clone());
+
+        GatewaySubscription { subscriber }
+    }
+
+    /// Number of active subscribers
+    pub fn subscriber_count(&self) -> usize {
+        let mut subscribers = self.subscribers();
+        subscribers.retain(|s| !s.queue().closed);
+        subscribers.len()
+    }
+
+    /// Deliver an event to all matching subscribers, waiting on any full subscribers with [`Overflow::Block`].
+    pub async fn route(&self, msg: ServerMsg) {
+        let matching: Vec<_> = {
+            let mut subscribers = self.subscribers();
+            subscribers.retain(|s| !s.queue().closed);
+            subscribers.iter().filter(|s| s.filter.matches(&msg)).cloned().collect()
+        };
+
+        if matching.is_empty() {
+            return;
+        }
+
+        let msg = Arc::new(msg);
+
+        for subscriber in matching {
+            if let Some(mut event) = subscriber.try_push(msg.clone(), None) {
+                futures::future::poll_fn(|cx| match subscriber.try_push(event.clone(), Some(cx)) {
+                    Some(e) => {
+                        event = e;
+                        Poll::Pending
+                    }
+                    None => Poll::Ready(()),
+                })
+                .await;
+            }
+        }
+    }
+
+    /// Route all events from the stream, such as a [`GatewayConnection`](super::GatewayConnection),
+    /// until it ends or yields an error.
+    ///
+    /// After an error, routing can be continued by calling this again with the same stream.
+    pub async fn run<S, E>(&self, stream: &mut S) -> Result<(), E>
+    where
+        S: Stream<Item = Result<ServerMsg, E>> + Unpin,
+    {
+        while let Some(msg) = stream.next().await {
+            self.route(msg?).await;
+        }
+
+        Ok(())
+    }
+}
+
+/// Stream of events from a [`GatewayRouter`], ending once disconnected or the router is dropped.
+///
+/// Dropping the subscription removes it from the router.
+pub struct GatewaySubscription {
+    subscriber: Arc<Subscriber>,
+}
+
+impl GatewaySubscription {
+    pub fn filter(&self) -> &EventFilter {
+        &self.subscriber.filter
+    }
+
+    /// Number of events dropped or not delivered because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.subscriber.queue().dropped
+    }
+
+    /// Whether the subscriber has been disconnected, although queued events may remain
+    pub fn is_closed(&self) -> bool {
+        self.subscriber.queue().closed
+    }
+}
+
+impl Drop for GatewaySubscription {
+    fn drop(&mut self) {
+        self.subscriber.close();
+    }
+}
+
+impl Stream for GatewaySubscription {
+    type Item = Arc<ServerMsg>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut queue = self.subscriber.queue();
+
+        if let Some(event) = queue.events.pop_front() {
+            // every waiting sender retries, as any of them may have given up in the meantime
+            queue.wake_senders();
+
+            return Poll::Ready(Some(event));
+        }
+
+        if queue.closed {
+            return Poll::Ready(None);
+        }
+
+        queue.rx_waker = Some(cx.waker().clone());
+
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::models::events::{MessageDeleteEvent, RoleDeleteEvent};
+    use crate::models::{Room, RoomFlags};
+
+    use super::*;
+
+    fn id(id: u64) -> Snowflake {
+        id.to_string().parse().unwrap()
+    }
+
+    fn message_delete(msg_id: u64, party_id: Option<u64>) -> ServerMsg {
+        ServerMsg::new_message_delete(MessageDeleteEvent {
+            id: id(msg_id),
+            room_id: id(10),
+            party_id: party_id.map(id),
+        })
+    }
+
+    fn room(room_id: u64, party_id: u64) -> Room {
+        Room {
+            id: id(room_id),
+            flags: RoomFlags::empty(),
+            party_id: Some(id(party_id)),
+            avatar: None,
+            name: "test".into(),
+            topic: None,
+            position: 0,
+            rate_limit_per_user: None,
+            parent_id: None,
+            overwrites: Default::default(),
+        }
+    }
+
+    fn msg_id(event: Option<Arc<ServerMsg>>) -> Option<Snowflake> {
+        match event.as_deref() {
+            Some(ServerMsg::MessageDelete(e)) => Some(e.id),
+            Some(ServerMsg::RoleDelete(e)) => Some(e.id),
+            Some(ServerMsg::RoomCreate(e)) => Some(e.inner.id),
+            Some(ServerMsg::RoomUpdate(e)) => Some(e.inner.id),
+            _ => None,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_gateway_router() {
+        let router = GatewayRouter::new();
+
+        let mut messages = router.subscribe(EventFilter::new(Intent::MESSAGES), 2, Overflow::DropOldest);
+        let mut party = router.subscribe(EventFilter::default().party(id(1)), 1, Overflow::Disconnect);
+        let mut roles = router.subscribe(EventFilter::new(Intent::PARTIES).party(id(1)), 1, Overflow::Block);
+        let mut rooms = router.subscribe(EventFilter::default().party(id(2)).room(id(20)), 4, Overflow::Disconnect);
+
+        router.route(ServerMsg::new_room_create(room(20, 2))).await;
+        router.route(ServerMsg::new_room_create(room(21, 2))).await;
+        router.route(ServerMsg::new_room_update(room(20, 2))).await;
+
+        assert_eq!(msg_id(rooms.next().await), Some(id(20)));
+        assert!(matches!(rooms.next().await.as_deref(), Some(ServerMsg::RoomUpdate(_))));
+        drop(rooms);
+
+        router.route(message_delete(1, Some(1))).await;
+        router.route(message_delete(2, None)).await;
+        router.route(message_delete(3, Some(1))).await;
+
+        // the oldest message was dropped, and the party subscriber disconnected on overflow
+        assert_eq!(messages.dropped(), 1);
+        assert!(party.is_closed());
+        assert_eq!(router.subscriber_count(), 2);
+
+        assert_eq!(msg_id(party.next().await), Some(id(1)));
+        assert_eq!(msg_id(party.next().await), None);
+
+        let role_delete = |role_id| {
+            ServerMsg::new_role_delete(RoleDeleteEvent {
+                id: id(role_id),
+                party_id: id(1),
+            })
+        };
+
+        router.route(role_delete(4)).await;
+
+        // the roles subscriber is full, so concurrent routing waits for it
+        let blocked = [5, 6].map(|role_id| {
+            let router = router.clone();
+            tokio::spawn(async move { router.route(role_delete(role_id)).await })
+        });
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(blocked.iter().all(|task| !task.is_finished()));
+
+        assert_eq!(msg_id(roles.next().await), Some(id(4)));
+
+        let delivered = [msg_id(roles.next().await), msg_id(roles.next().await)];
+        assert!(delivered.contains(&Some(id(5))) && delivered.contains(&Some(id(6))));
+
+        for task in blocked {
+            task.await.unwrap();
+        }
+
+        drop(router);
+
+        assert_eq!(msg_id(messages.next().await), Some(id(2)));
+        assert_eq!(msg_id(messages.next().await), Some(id(3)));
+        assert_eq!(msg_id(messages.next().await), None);
+        assert_eq!(msg_id(roles.next().await), None);
+    }
+}
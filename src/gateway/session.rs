@@ -0,0 +1,126 @@
+use crate::models::{
+    gateway::message::{ClientMsg, ServerMsg},
+    Snowflake,
+};
+
+/// How the current gateway session was established, see [`GatewayConnectionControl::session_start`](super::GatewayConnectionControl::session_start)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SessionStart {
+    /// A new session was started with [`ClientMsg::Identify`] and a full [`Ready`](ServerMsg::Ready) was received,
+    /// so any cached state should be rebuilt.
+    Identified,
+
+    /// The previous session was continued with [`ClientMsg::Resume`] after a reconnect,
+    /// so events missed while disconnected were replayed and cached state is still valid.
+    Resumed,
+}
+
+/// Change in the session caused by a message, see [`SessionTracker::on_message`]
+#[derive(Debug)]
+pub(crate) enum SessionUpdate {
+    /// A [`ClientMsg::Resume`] to send in response to [`Hello`](ServerMsg::Hello)
+    Resume(ClientMsg),
+
+    /// A new session was started
+    Identified,
+
+    /// The server accepted the resume
+    Resumed,
+}
+
+/// Tracks the gateway session across reconnects, to decide whether to Resume or Identify
+#[derive(Debug, Default)]
+pub(crate) struct SessionTracker {
+    pub session: Option<Snowflake>,
+    pub start: Option<SessionStart>,
+    pub resuming: bool,
+}
+
+impl SessionTracker {
+    /// Observes a message received from the server, returning how it changed the session, if at all.
+    ///
+    /// Each session start is reported exactly once, and a [`ClientMsg::Resume`] is returned in response
+    /// to [`Hello`](ServerMsg::Hello) if there is a session to resume.
+    pub fn on_message(&mut self, msg: &ServerMsg) -> Option<SessionUpdate> {
+        match msg {
+            ServerMsg::Hello(_) => {
+                self.resuming = self.session.is_some();
+
+                return self.session.map(|session| SessionUpdate::Resume(ClientMsg::new_resume(session)));
+            }
+            ServerMsg::Ready(ready) => {
+                self.session = Some(ready.session);
+                self.start = Some(SessionStart::Identified);
+                self.resuming = false;
+
+                return Some(SessionUpdate::Identified);
+            }
+            ServerMsg::InvalidSession(_) => self.clear(),
+            ServerMsg::HeartbeatAck(_) => {}
+            // any other event after resuming means the server accepted it
+            _ if self.resuming => {
+                self.start = Some(SessionStart::Resumed);
+                self.resuming = false;
+
+                return Some(SessionUpdate::Resumed);
+            }
+            _ => {}
+        }
+
+        None
+    }
+
+    pub fn clear(&mut self) {
+        *self = SessionTracker::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::models::gateway::events::Hello;
+
+    #[test]
+    fn test_session_resume() {
+        let mut tracker = SessionTracker::default();
+
+        // first connection must identify
+        assert!(tracker.on_message(&ServerMsg::new_hello(Hello::default())).is_none());
+        assert!(!tracker.resuming);
+
+        let session: Snowflake = "1".parse().unwrap();
+        tracker.session = Some(session);
+        tracker.start = Some(SessionStart::Identified);
+
+        // reconnect
+        match tracker.on_message(&ServerMsg::new_hello(Hello::default())) {
+            Some(SessionUpdate::Resume(ClientMsg::Resume(payload))) => assert_eq!(payload.session, session),
+            msg => panic!("expected resume, got {msg:?}"),
+        }
+        assert!(tracker.resuming);
+
+        // two resumes in a row, each reported only once by the first event after any acks
+        for _ in 0..2 {
+            assert!(tracker.on_message(&ServerMsg::new_hello(Hello::default())).is_some());
+
+            assert!(tracker.on_message(&ServerMsg::new_heartbeat_ack()).is_none());
+            assert!(tracker.resuming);
+
+            let update = tracker.on_message(&ServerMsg::new_relation_remove(session));
+            assert!(matches!(update, Some(SessionUpdate::Resumed)));
+            assert!(!tracker.resuming);
+            assert_eq!(tracker.start, Some(SessionStart::Resumed));
+
+            assert!(tracker.on_message(&ServerMsg::new_relation_remove(session)).is_none());
+        }
+
+        // resume rejected, so falls back to identify
+        tracker.on_message(&ServerMsg::new_hello(Hello::default()));
+        tracker.on_message(&ServerMsg::new_invalid_session());
+
+        assert_eq!(tracker.session, None);
+        assert_eq!(tracker.start, None);
+        assert!(tracker.on_message(&ServerMsg::new_hello(Hello::default())).is_none());
+    }
+}
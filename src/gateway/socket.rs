@@ -1,98 +1,84 @@
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 
 use futures::{Sink, Stream};
-use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
-use tokio_tungstenite::tungstenite::Message as WsMessage;
 
 type WebSocket = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
 
 use crate::driver::{Driver, Encoding};
 use crate::models::gateway::message::{ClientMsg, ServerMsg};
 
-use super::error::GatewayErrorCode;
-use super::GatewayError;
+use super::codec::GatewayCodec;
+use super::{GatewayConfig, GatewayError, GatewayStats};
 
 pin_project_lite::pin_project! {
     /// Raw WebSocket adapter that handles encoding and decoding of messages
     pub struct GatewaySocket {
         #[pin]
         ws: WebSocket,
-        encoding: Encoding,
-        compress: bool,
+        codec: GatewayCodec,
     }
 }
 
 impl GatewaySocket {
+    /// Connects with the driver's preferred encoding and default compression
     pub async fn connect(driver: Driver) -> Result<Self, GatewayError> {
-        // TODO: Setup a circuit breaker to run this in a loop until it succeeds
-        let (ws, _) = tokio_tungstenite::connect_async(format!(
-            "ws{}/api/v1/gateway?compress=true&encoding={}",
-            &driver.uri[4..],
-            match driver.encoding {
-                Encoding::JSON => "json",
-                #[cfg(feature = "cbor")]
-                Encoding::CBOR => "cbor",
-            }
-        ))
-        .await?;
-
-        Ok(GatewaySocket {
-            ws,
+        let config = GatewayConfig {
             encoding: driver.encoding,
-            compress: true,
-        })
+            ..GatewayConfig::DEFAULT
+        };
+
+        Self::connect_with_config(driver, config).await
     }
 
-    fn encode(&self, msg: ClientMsg) -> Result<WsMessage, GatewayError> {
-        let mut body = Vec::new();
+    pub async fn connect_with_config(driver: Driver, config: GatewayConfig) -> Result<Self, GatewayError> {
+        let mut url = driver.gateway_url()?;
 
-        match self.encoding {
-            Encoding::JSON => serde_json::to_writer(&mut body, &msg)?,
-            #[cfg(feature = "cbor")]
-            Encoding::CBOR => ciborium::ser::into_writer(&msg, &mut body)?,
-        }
+        let params = config.query_params();
+
+        url.query_pairs_mut()
+            .append_pair(
+                "encoding",
+                match params.encoding {
+                    Encoding::JSON => "json",
+                    #[cfg(feature = "cbor")]
+                    Encoding::CBOR => "cbor",
+                },
+            )
+            .append_pair("compress", if params.compress { "true" } else { "false" });
 
-        if self.compress {
-            body = miniz_oxide::deflate::compress_to_vec_zlib(&body, 9);
+        if params.stream {
+            url.query_pairs_mut().append_pair("stream", "true");
         }
 
-        Ok(WsMessage::Binary(body))
-    }
+        let (ws, _) = tokio_tungstenite::connect_async(url.as_str()).await?;
 
-    fn decode(&self, msg: WsMessage) -> Result<ServerMsg, GatewayError> {
-        match &msg {
-            WsMessage::Close(None) => return Err(GatewayError::Disconnected),
-            WsMessage::Close(Some(msg)) => {
-                return Err(match msg.code {
-                    CloseCode::Library(code) => {
-                        use num_traits::FromPrimitive;
-
-                        GatewayError::CloseError(match GatewayErrorCode::from_u16(code) {
-                            Some(code) => code,
-                            None => GatewayErrorCode::UnknownError,
-                        })
-                    }
-                    _ => GatewayError::Disconnected,
-                });
-            }
-            _ => {}
-        }
+        Ok(GatewaySocket {
+            ws,
+            codec: GatewayCodec::new(config),
+        })
+    }
 
-        let mut body = msg.into_data();
+    #[inline]
+    pub fn config(&self) -> &GatewayConfig {
+        &self.codec.config
+    }
 
-        if self.compress {
-            body = match miniz_oxide::inflate::decompress_to_vec_zlib(&body) {
-                Ok(body) => body,
-                Err(_) => return Err(GatewayError::CompressionError),
-            };
-        }
+    /// Record traffic on this socket to the given stats
+    pub(crate) fn with_stats(mut self, stats: Arc<GatewayStats>) -> Self {
+        self.codec.stats = Some(stats);
+        self
+    }
+}
 
-        Ok(match self.encoding {
-            Encoding::JSON => serde_json::from_slice(&body)?,
-            #[cfg(feature = "cbor")]
-            Encoding::CBOR => ciborium::de::from_reader(&body[..])?,
-        })
+impl GatewaySocket {
+    /// Same as [`Sink::start_send`], but leaves the message with the caller, such as to record it once sent
+    #[allow(clippy::result_large_err)] // same as the Sink
+    pub(crate) fn start_send_ref(self: Pin<&mut Self>, msg: &ClientMsg) -> Result<(), GatewayError> {
+        let this = self.project();
+        let item = this.codec.encode(msg)?;
+        this.ws.start_send(item).map_err(GatewayError::from)
     }
 }
 
@@ -106,8 +92,7 @@ impl Sink<ClientMsg> for GatewaySocket {
 
     #[inline]
     fn start_send(self: Pin<&mut Self>, msg: ClientMsg) -> Result<(), GatewayError> {
-        let item = self.encode(msg)?;
-        self.project().ws.start_send(item).map_err(GatewayError::from)
+        self.start_send_ref(&msg)
     }
 
     #[inline]
@@ -125,12 +110,12 @@ impl Stream for GatewaySocket {
     type Item = Result<ServerMsg, GatewayError>;
 
     #[inline]
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
-        let this = self.as_mut().project();
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.project();
 
         Poll::Ready(match this.ws.poll_next(cx) {
             Poll::Ready(None) => None,
-            Poll::Ready(Some(Ok(msg))) => Some(self.decode(msg)),
+            Poll::Ready(Some(Ok(msg))) => Some(this.codec.decode(msg)),
             Poll::Ready(Some(Err(e))) => Some(Err(e.into())),
             Poll::Pending => return Poll::Pending,
         })
@@ -0,0 +1,223 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Number of heartbeat round-trip times kept in [`GatewayStats::latency_history`]
+pub const LATENCY_HISTORY_LEN: usize = 16;
+
+const NONE: u64 = u64::MAX;
+
+/// Gateway connection health metrics, updated lock-free by the connection and readable from any task.
+///
+/// See [`GatewayConnectionControl::stats`](super::GatewayConnectionControl::stats)
+pub struct GatewayStats {
+    created: Instant,
+
+    latency: [AtomicU64; LATENCY_HISTORY_LEN],
+    latency_count: AtomicUsize,
+
+    connects: AtomicU64,
+    identifies: AtomicU64,
+    resumes: AtomicU64,
+
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_sent_uncompressed: AtomicU64,
+    bytes_received: AtomicU64,
+    bytes_received_uncompressed: AtomicU64,
+    decode_errors: AtomicU64,
+
+    /// Microseconds since `created`, or `NONE`
+    last_event: AtomicU64,
+}
+
+/// Point-in-time copy of [`GatewayStats`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct GatewayStatsSnapshot {
+    /// Heartbeat round-trip times, oldest first
+    pub latency_history: Vec<Duration>,
+
+    /// Number of times the websocket was (re)connected
+    pub connects: u64,
+
+    /// Number of new sessions started with a full [Ready](crate::models::gateway::message::ServerMsg::Ready)
+    pub identifies: u64,
+
+    /// Number of sessions successfully resumed after reconnecting
+    pub resumes: u64,
+
+    pub messages_sent: u64,
+    pub messages_received: u64,
+
+    /// Bytes sent over the websocket, after compression
+    pub bytes_sent: u64,
+    pub bytes_sent_uncompressed: u64,
+
+    /// Bytes received over the websocket, before decompression
+    pub bytes_received: u64,
+    pub bytes_received_uncompressed: u64,
+
+    /// Number of messages that could not be decompressed or decoded
+    pub decode_errors: u64,
+
+    /// Time since the last message was received, if any
+    pub since_last_event: Option<Duration>,
+}
+
+impl Default for GatewayStats {
+    fn default() -> Self {
+        GatewayStats {
+            created: Instant::now(),
+            latency: std::array::from_fn(|_| AtomicU64::new(NONE)),
+            latency_count: AtomicUsize::new(0),
+            connects: AtomicU64::new(0),
+            identifies: AtomicU64::new(0),
+            resumes: AtomicU64::new(0),
+            messages_sent: AtomicU64::new(0),
+            messages_received: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            bytes_sent_uncompressed: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            bytes_received_uncompressed: AtomicU64::new(0),
+            decode_errors: AtomicU64::new(0),
+            last_event: AtomicU64::new(NONE),
+        }
+    }
+}
+
+#[inline]
+fn micros(duration: Duration) -> u64 {
+    u64::try_from(duration.as_micros()).unwrap_or(NONE - 1)
+}
+
+impl GatewayStats {
+    /// Round-trip time of the last acknowledged heartbeat
+    pub fn latency(&self) -> Option<Duration> {
+        let count = self.latency_count.load(Ordering::Acquire);
+
+        match count.checked_sub(1) {
+            Some(last) => match self.latency[last % LATENCY_HISTORY_LEN].load(Ordering::Relaxed) {
+                NONE => None,
+                us => Some(Duration::from_micros(us)),
+            },
+            None => None,
+        }
+    }
+
+    /// Up to [`LATENCY_HISTORY_LEN`] of the most recent heartbeat round-trip times, oldest first
+    pub fn latency_history(&self) -> Vec<Duration> {
+        let count = self.latency_count.load(Ordering::Acquire);
+        let start = count.saturating_sub(LATENCY_HISTORY_LEN);
+
+        (start..count)
+            .filter_map(|i| match self.latency[i % LATENCY_HISTORY_LEN].load(Ordering::Relaxed) {
+                NONE => None,
+                us => Some(Duration::from_micros(us)),
+            })
+            .collect()
+    }
+
+    /// Number of times the websocket was reconnected, after the first connection
+    pub fn reconnects(&self) -> u64 {
+        self.connects.load(Ordering::Relaxed).saturating_sub(1)
+    }
+
+    /// Time since the last message was received, if any
+    pub fn since_last_event(&self) -> Option<Duration> {
+        match self.last_event.load(Ordering::Relaxed) {
+            NONE => None,
+            us => Some(self.created.elapsed().saturating_sub(Duration::from_micros(us))),
+        }
+    }
+
+    pub fn snapshot(&self) -> GatewayStatsSnapshot {
+        GatewayStatsSnapshot {
+            latency_history: self.latency_history(),
+            connects: self.connects.load(Ordering::Relaxed),
+            identifies: self.identifies.load(Ordering::Relaxed),
+            resumes: self.resumes.load(Ordering::Relaxed),
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_sent_uncompressed: self.bytes_sent_uncompressed.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            bytes_received_uncompressed: self.bytes_received_uncompressed.load(Ordering::Relaxed),
+            decode_errors: self.decode_errors.load(Ordering::Relaxed),
+            since_last_event: self.since_last_event(),
+        }
+    }
+
+    pub(crate) fn record_latency(&self, latency: Duration) {
+        let idx = self.latency_count.load(Ordering::Relaxed);
+
+        // only the connection writes, so this doesn't need to be a compare-exchange
+        self.latency[idx % LATENCY_HISTORY_LEN].store(micros(latency), Ordering::Relaxed);
+        self.latency_count.store(idx + 1, Ordering::Release);
+    }
+
+    pub(crate) fn record_connect(&self) {
+        self.connects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_identify(&self) {
+        self.identifies.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_resume(&self) {
+        self.resumes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_sent(&self, compressed: usize, uncompressed: usize) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(compressed as u64, Ordering::Relaxed);
+        self.bytes_sent_uncompressed.fetch_add(uncompressed as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_received(&self, compressed: usize, uncompressed: usize) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received.fetch_add(compressed as u64, Ordering::Relaxed);
+        self.bytes_received_uncompressed.fetch_add(uncompressed as u64, Ordering::Relaxed);
+        self.last_event.store(micros(self.created.elapsed()), Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_decode_error(&self) {
+        self.decode_errors.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_gateway_stats() {
+        let stats = GatewayStats::default();
+
+        assert_eq!(stats.latency(), None);
+        assert_eq!(stats.since_last_event(), None);
+
+        for ms in 0..20 {
+            stats.record_latency(Duration::from_millis(ms));
+        }
+
+        let history = stats.latency_history();
+        assert_eq!(history.len(), LATENCY_HISTORY_LEN);
+        assert_eq!(history[0], Duration::from_millis(4));
+        assert_eq!(stats.latency(), Some(Duration::from_millis(19)));
+
+        stats.record_connect();
+        stats.record_connect();
+        assert_eq!(stats.reconnects(), 1);
+
+        stats.record_received(100, 300);
+        tokio::time::advance(Duration::from_secs(2)).await;
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.bytes_received, 100);
+        assert_eq!(snapshot.bytes_received_uncompressed, 300);
+        assert_eq!(snapshot.messages_received, 1);
+        assert_eq!(snapshot.since_last_event, Some(Duration::from_secs(2)));
+    }
+}
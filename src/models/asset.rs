@@ -61,6 +61,19 @@ impl AssetFlags {
         }
     }
 
+    pub const fn with_animated(&self, animated: bool) -> Self {
+        if animated {
+            self.union(Self::ANIMATED)
+        } else {
+            self.difference(Self::ANIMATED)
+        }
+    }
+
+    /// Replaces the accepted formats, ignoring any non-format flags in `formats`
+    pub const fn with_formats(&self, formats: AssetFlags) -> Self {
+        self.difference(Self::FORMATS).union(formats.intersection(Self::FORMATS))
+    }
+
     pub const fn quality(&self) -> u8 {
         self.intersection(Self::QUALITY).bits as u8
     }
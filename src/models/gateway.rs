@@ -309,6 +309,76 @@ pub mod message {
         *value == T::default()
     }
 
+    /// Undecoded payload of a message with an unknown opcode, kept in the encoding it was received in
+    /// so that it can be decoded once the message is supported.
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum RawPayload {
+        Json(serde_json::Value),
+
+        #[cfg(feature = "cbor")]
+        Cbor(ciborium::Value),
+    }
+
+    impl Default for RawPayload {
+        fn default() -> Self {
+            RawPayload::Json(serde_json::Value::Null)
+        }
+    }
+
+    impl RawPayload {
+        /// Returns true if there was no payload
+        pub fn is_null(&self) -> bool {
+            match self {
+                RawPayload::Json(value) => value.is_null(),
+                #[cfg(feature = "cbor")]
+                RawPayload::Cbor(value) => value.is_null(),
+            }
+        }
+    }
+
+    impl From<serde_json::Value> for RawPayload {
+        #[inline]
+        fn from(value: serde_json::Value) -> Self {
+            RawPayload::Json(value)
+        }
+    }
+
+    #[cfg(feature = "cbor")]
+    impl From<ciborium::Value> for RawPayload {
+        #[inline]
+        fn from(value: ciborium::Value) -> Self {
+            RawPayload::Cbor(value)
+        }
+    }
+
+    impl Serialize for RawPayload {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            match self {
+                RawPayload::Json(value) => value.serialize(serializer),
+                #[cfg(feature = "cbor")]
+                RawPayload::Cbor(value) => value.serialize(serializer),
+            }
+        }
+    }
+
+    impl<'de> Deserialize<'de> for RawPayload {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            // binary encodings may contain values not representable in JSON, such as byte strings
+            #[cfg(feature = "cbor")]
+            if !deserializer.is_human_readable() {
+                return ciborium::Value::deserialize(deserializer).map(RawPayload::Cbor);
+            }
+
+            serde_json::Value::deserialize(deserializer).map(RawPayload::Json)
+        }
+    }
+
     macro_rules! decl_msgs {
         (
             $(#[$meta:meta])*
@@ -327,6 +397,11 @@ pub mod message {
             #[repr(u8)]
             pub enum [<$name Opcode>] {
                 $($opcode = $code,)*
+
+                /// Placeholder for messages with an opcode unknown to this version, which is never sent as-is.
+                #[doc = ""]
+                #[doc = "See [" $name "::raw_opcode] for the opcode received."]
+                Unknown = 255,
             }
 
             pub mod [<$name:snake _payloads>] {
@@ -366,6 +441,20 @@ pub mod message {
                         }
                     )?)*
                 )*
+
+                #[doc = "Payload struct for [" $name "::Unknown]"]
+                #[derive(Debug, Serialize, Deserialize)]
+                #[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+                #[cfg_attr(feature = "rkyv", derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize))]
+                #[cfg_attr(feature = "rkyv", archive(check_bytes))]
+                pub struct UnknownPayload {
+                    pub opcode: u8,
+
+                    /// Undecoded payload, or `null` if there was none
+                    #[cfg_attr(feature = "schema", schemars(with = "serde_json::Value"))]
+                    #[cfg_attr(feature = "rkyv", with(rkyv::with::Skip))]
+                    pub payload: RawPayload,
+                }
             }
 
             #[cfg(feature = "framework")]
@@ -377,6 +466,8 @@ pub mod message {
                 $(
                     [<$opcode:snake _handler>]: Option<Box<dyn Fn(Arc<S>, C, $($ty,)*) -> BoxFuture<'static, U> + Send + Sync>>,
                 )*
+
+                unknown_handler: Option<Box<dyn Fn(Arc<S>, C, u8, RawPayload) -> BoxFuture<'static, U> + Send + Sync>>,
             }
 
             #[cfg(feature = "framework")]
@@ -412,6 +503,7 @@ pub mod message {
                         state: state.into(),
                         fallback,
                         $([<$opcode:snake _handler>]: None,)*
+                        unknown_handler: None,
                     }
                 }
 
@@ -420,6 +512,19 @@ pub mod message {
                     &self.state
                 }
 
+                /// Opcodes with a registered handler, not counting the fallback or unknown handlers
+                pub fn handled_opcodes(&self) -> Vec<[<$name Opcode>]> {
+                    let mut opcodes = Vec::new();
+
+                    $(
+                        if self.[<$opcode:snake _handler>].is_some() {
+                            opcodes.push([<$name Opcode>]::$opcode);
+                        }
+                    )*
+
+                    opcodes
+                }
+
                 $(
                     pub fn [<on_ $opcode:snake>]<F, R>(&mut self, cb: F) -> &mut Self
                     where
@@ -435,6 +540,17 @@ pub mod message {
                         self
                     }
                 )*
+
+                pub fn on_unknown<F, R>(&mut self, cb: F) -> &mut Self
+                where
+                    F: Fn(Arc<S>, C, u8, RawPayload) -> R + Send + Sync + 'static,
+                    R: Future<Output = U> + Send + 'static,
+                {
+                    assert!(self.unknown_handler.is_none(), "Cannot have more than one listener for on_unknown");
+
+                    self.unknown_handler = Some(Box::new(move |this, ctx, opcode, payload| Box::pin(cb(this, ctx, opcode, payload))));
+                    self
+                }
             }
 
             #[cfg(feature = "framework")]
@@ -456,6 +572,17 @@ pub mod message {
                         }
                     }
                 )*
+
+                fn unknown<'life0, 'async_trait>(&'life0 self, ctx: C, opcode: u8, payload: RawPayload)
+                    -> std::pin::Pin<Box<dyn Future<Output = U> + Send + 'async_trait>>
+                where
+                    'life0: 'async_trait, Self: 'async_trait,
+                {
+                    match self.unknown_handler {
+                        Some(ref cb) => cb(self.state.clone(), ctx, opcode, payload),
+                        None => (self.fallback)(self.state.clone(), ctx, $name::Unknown(Box::new([<$name:snake _payloads>]::UnknownPayload { opcode, payload })))
+                    }
+                }
             }
 
             #[doc = "Handler callbacks for [" $name "]"]
@@ -472,6 +599,11 @@ pub mod message {
                         $($name::$opcode([<$name:snake _payloads>]::[<$opcode Payload>] { $($field,)* }) => {
                             self.[<$opcode:snake>](ctx, $($field,)*)
                         })*
+                        $name::Unknown(msg) => {
+                            let [<$name:snake _payloads>]::UnknownPayload { opcode, payload } = *msg;
+
+                            self.unknown(ctx, opcode, payload)
+                        }
                     }
                 }
 
@@ -491,6 +623,16 @@ pub mod message {
                         self.fallback(ctx, $name::$opcode([<$name:snake _payloads>]::[<$opcode Payload>] { $($field,)* }))
                     }
                 )*
+
+                #[doc = "Handler callback for [" $name "::Unknown]"]
+                #[inline(always)]
+                fn unknown<'life0, 'async_trait>(&'life0 self, ctx: C, opcode: u8, payload: RawPayload)
+                    -> std::pin::Pin<Box<dyn Future<Output = U> + Send + 'async_trait>>
+                where
+                    'life0: 'async_trait, Self: 'async_trait,
+                {
+                    self.fallback(ctx, $name::Unknown(Box::new([<$name:snake _payloads>]::UnknownPayload { opcode, payload })))
+                }
             }
 
             $(#[$meta])*
@@ -506,13 +648,29 @@ pub mod message {
                     #[cfg_attr(feature = "schema", schemars(description = "" $name "::" $opcode "" ))]
                     $opcode([<$name:snake _payloads>]::[<$opcode Payload>])
                 ,)*
+
+                /// Message with an opcode unknown to this version, such as one added in a newer protocol version,
+                /// carrying the undecoded payload.
+                #[doc = ""]
+                #[doc = "See [new_unknown](" $name "::new_unknown) for an easy way to create this message."]
+                #[cfg_attr(feature = "schema", schemars(description = "" $name "::Unknown"))]
+                Unknown(Box<[<$name:snake _payloads>]::UnknownPayload>),
             }
 
             impl $name {
-                /// Returns the discrete opcode for the message
+                /// Returns the discrete opcode for the message, or `Unknown` if the opcode is unknown to this version
                 pub const fn opcode(&self) -> [<$name Opcode>] {
                     match self {
                         $($name::$opcode(_) => [<$name Opcode>]::$opcode,)*
+                        $name::Unknown(_) => [<$name Opcode>]::Unknown,
+                    }
+                }
+
+                /// Returns the opcode as sent over the gateway, including unknown opcodes
+                pub const fn raw_opcode(&self) -> u8 {
+                    match self {
+                        $($name::$opcode(_) => $code,)*
+                        $name::Unknown(payload) => payload.opcode,
                     }
                 }
             }
@@ -536,6 +694,18 @@ pub mod message {
                         })
                     }
                 )*
+
+                #[doc = "Create new [Unknown](" $name "::Unknown) message from a raw opcode and payload."]
+                #[doc = ""]
+                /// This can be used to send messages not yet supported by this version, but note that
+                /// a known opcode will be decoded as its regular message on the other end.
+                #[inline]
+                pub fn new_unknown(opcode: u8, payload: impl Into<RawPayload>) -> Self {
+                    $name::Unknown(Box::new([<$name:snake _payloads>]::UnknownPayload {
+                        opcode,
+                        payload: payload.into(),
+                    }))
+                }
             }
 
             impl Serialize for $name {
@@ -557,7 +727,21 @@ pub mod message {
 
                             state
                         }
-                    )*};
+                    )*
+                        $name::Unknown(payload) => {
+                            let skip_payload = payload.payload.is_null();
+
+                            let mut state = serializer.serialize_struct(stringify!($name), 2 - skip_payload as usize)?;
+
+                            state.serialize_field("o", &payload.opcode)?;
+
+                            if !skip_payload {
+                                state.serialize_field("p", &payload.payload)?;
+                            }
+
+                            state
+                        }
+                    };
 
                     state.end()
                 }
@@ -571,12 +755,38 @@ pub mod message {
                     use std::fmt;
 
                     #[derive(Clone, Copy, Deserialize)]
+                    #[serde(field_identifier)]
                     enum Field {
                         #[serde(rename = "o")]
                         Opcode,
 
                         #[serde(rename = "p")]
                         Payload,
+
+                        #[serde(other)]
+                        Other,
+                    }
+
+                    /// Reads the payload, if any, skipping over fields added in newer protocol versions
+                    fn next_payload<'de, A, T>(map: &mut A) -> Result<Option<T>, A::Error>
+                    where
+                        A: MapAccess<'de>,
+                        T: Deserialize<'de>,
+                    {
+                        let mut payload = None;
+
+                        while let Some(field) = map.next_key()? {
+                            match field {
+                                Field::Payload if payload.is_none() => payload = Some(map.next_value()?),
+                                Field::Payload => return Err(de::Error::duplicate_field("p")),
+                                Field::Opcode => return Err(de::Error::duplicate_field("o")),
+                                Field::Other => {
+                                    map.next_value::<de::IgnoredAny>()?;
+                                }
+                            }
+                        }
+
+                        Ok(payload)
                     }
 
                     struct MessageVisitor;
@@ -592,22 +802,25 @@ pub mod message {
                         where
                             V: MapAccess<'de>,
                         {
-                            let opcode = match map.next_entry()? {
+                            let opcode: u8 = match map.next_entry()? {
                                 Some((Field::Opcode, o)) => o,
                                 _ => return Err(de::Error::custom("Missing opcode first")),
                             };
 
                             match opcode {
                                 $(
-                                    [<$name Opcode>]::$opcode => Ok($name::$opcode(match map.next_entry()? {
-                                        Some((Field::Payload, payload)) => payload,
+                                    $code => Ok($name::$opcode(match next_payload(&mut map)? {
+                                        Some(payload) => payload,
                                         $(None => $Default::default(),)?
 
                                         #[allow(unreachable_patterns)]
                                         _ => return Err(de::Error::missing_field("payload")),
                                     })),
                                 )*
-                                // _ => Err(de::Error::custom("Invalid opcode")),
+                                _ => Ok($name::Unknown(Box::new([<$name:snake _payloads>]::UnknownPayload {
+                                    opcode,
+                                    payload: next_payload(&mut map)?.unwrap_or_default(),
+                                }))),
                             }
                         }
 
@@ -615,22 +828,31 @@ pub mod message {
                         where
                             A: SeqAccess<'de>
                         {
-                            let opcode: [<$name Opcode>] = match seq.next_element()? {
+                            let opcode: u8 = match seq.next_element()? {
                                 Some(o) => o,
                                 _ => return Err(de::Error::custom("Missing opcode first")),
                             };
 
-                            match opcode {
+                            let msg = match opcode {
                                 $(
-                                    [<$name Opcode>]::$opcode => Ok($name::$opcode(match seq.next_element()? {
+                                    $code => $name::$opcode(match seq.next_element()? {
                                         Some(payload) => payload,
                                         $(None => $Default::default(),)?
 
                                         #[allow(unreachable_patterns)]
                                         _ => return Err(de::Error::missing_field("payload")),
-                                    })),
+                                    }),
                                 )*
-                            }
+                                _ => $name::Unknown(Box::new([<$name:snake _payloads>]::UnknownPayload {
+                                    opcode,
+                                    payload: seq.next_element()?.unwrap_or_default(),
+                                })),
+                            };
+
+                            // skip over elements added in newer protocol versions
+                            while seq.next_element::<de::IgnoredAny>()?.is_some() {}
+
+                            Ok(msg)
                         }
                     }
 
@@ -646,11 +868,9 @@ pub mod message {
     use crate::models::{
         commands::{Identify, SetPresence},
         events::*,
-        Arc, Intent, Message as RoomMessage, Party, PartyMember, Relationship, Role, User, UserPresence,
+        Arc, Intent, Message as RoomMessage, Party, PartyMember, Relationship, Role, Room, User, UserPresence,
     };
 
-    type Room = (); // TODO
-
     // TODO: Check that this enum doesn't grow too large, allocate large payloads like Ready
     decl_msgs! {
         /// Messages send from the server to the client
@@ -717,63 +937,74 @@ pub mod message {
         }
     }
 
-    impl ServerMsg {
+    impl ServerMsgOpcode {
+        /// Intents required to receive every event with this opcode, or `None` if they are always sent
         #[rustfmt::skip]
-        pub fn matching_intent(&self) -> Option<Intent> {
-            Some(match *self {
-                | ServerMsg::PartyCreate { .. }
-                | ServerMsg::PartyDelete { .. }
-                | ServerMsg::PartyUpdate { .. }
-                | ServerMsg::RoleCreate { .. }
-                | ServerMsg::RoleDelete { .. }
-                | ServerMsg::RoleUpdate { .. }
-                | ServerMsg::RoomPinsUpdate { .. }
-                | ServerMsg::RoomCreate { .. }
-                | ServerMsg::RoomDelete { .. }
-                | ServerMsg::RoomUpdate { .. }
+        pub fn matching_intent(self) -> Option<Intent> {
+            Some(match self {
+                | ServerMsgOpcode::PartyCreate
+                | ServerMsgOpcode::PartyDelete
+                | ServerMsgOpcode::PartyUpdate
+                | ServerMsgOpcode::RoleCreate
+                | ServerMsgOpcode::RoleDelete
+                | ServerMsgOpcode::RoleUpdate
+                | ServerMsgOpcode::RoomPinsUpdate
+                | ServerMsgOpcode::RoomCreate
+                | ServerMsgOpcode::RoomDelete
+                | ServerMsgOpcode::RoomUpdate
                     => Intent::PARTIES,
 
-                | ServerMsg::MemberAdd { .. }
-                | ServerMsg::MemberUpdate { .. }
-                | ServerMsg::MemberRemove { .. }
+                | ServerMsgOpcode::MemberAdd
+                | ServerMsgOpcode::MemberUpdate
+                | ServerMsgOpcode::MemberRemove
                     => Intent::PARTY_MEMBERS,
 
-                | ServerMsg::MemberBan {..}
-                | ServerMsg::MemberUnban {..}
+                | ServerMsgOpcode::MemberBan
+                | ServerMsgOpcode::MemberUnban
                     => Intent::PARTY_BANS,
 
-                | ServerMsg::MessageCreate { .. }
-                | ServerMsg::MessageDelete { .. }
-                | ServerMsg::MessageUpdate { .. }
+                | ServerMsgOpcode::MessageCreate
+                | ServerMsgOpcode::MessageDelete
+                | ServerMsgOpcode::MessageUpdate
                     => Intent::MESSAGES,
 
-                | ServerMsg::MessageReactionAdd { .. }
-                | ServerMsg::MessageReactionRemove { .. }
-                | ServerMsg::MessageReactionRemoveAll { .. }
-                | ServerMsg::MessageReactionRemoveEmote { .. }
+                | ServerMsgOpcode::MessageReactionAdd
+                | ServerMsgOpcode::MessageReactionRemove
+                | ServerMsgOpcode::MessageReactionRemoveAll
+                | ServerMsgOpcode::MessageReactionRemoveEmote
                     => Intent::MESSAGE_REACTIONS,
 
-                ServerMsg::PresenceUpdate { .. }
+                ServerMsgOpcode::PresenceUpdate
                     => Intent::PRESENCE,
 
-                ServerMsg::TypingStart { .. }
+                ServerMsgOpcode::TypingStart
                     => Intent::MESSAGE_TYPING,
 
-                ServerMsg::ProfileUpdate(ref payload) => match payload.inner.party_id {
-                    Some(_) => Intent::PROFILE_UPDATES | Intent::PARTY_MEMBERS,
-                    None => Intent::PROFILE_UPDATES,
-                }
-
-                | ServerMsg::Hello { .. }
-                | ServerMsg::HeartbeatAck { .. }
-                | ServerMsg::Ready { .. }
-                | ServerMsg::UserUpdate { .. }
-                | ServerMsg::InvalidSession { .. }
-                | ServerMsg::RelationAdd { .. }
-                | ServerMsg::RelationRemove { .. }
+                // profile updates within a party also require PARTY_MEMBERS
+                ServerMsgOpcode::ProfileUpdate
+                    => Intent::PROFILE_UPDATES | Intent::PARTY_MEMBERS,
+
+                | ServerMsgOpcode::Hello
+                | ServerMsgOpcode::HeartbeatAck
+                | ServerMsgOpcode::Ready
+                | ServerMsgOpcode::UserUpdate
+                | ServerMsgOpcode::InvalidSession
+                | ServerMsgOpcode::RelationAdd
+                | ServerMsgOpcode::RelationRemove
+                | ServerMsgOpcode::Unknown
                     => return None,
             })
         }
+    }
+
+    impl ServerMsg {
+        /// Intents required to receive this event, or `None` if it is always sent
+        pub fn matching_intent(&self) -> Option<Intent> {
+            match self {
+                ServerMsg::ProfileUpdate(payload) if payload.inner.party_id.is_none() => Some(Intent::PROFILE_UPDATES),
+                _ => self.opcode().matching_intent(),
+            }
+        }
 
         /// If the event originated from a specific user, get their ID
         pub fn user_id(&self) -> Option<Snowflake> {
@@ -794,6 +1025,73 @@ pub mod message {
                 _ => return None,
             })
         }
+
+        /// If the event relates to a specific party, get its ID
+        pub fn party_id(&self) -> Option<Snowflake> {
+            Some(match self {
+                ServerMsg::PartyCreate(p) => p.partial.id,
+                ServerMsg::PartyUpdate(p) => match *p.inner {
+                    PartyUpdateEvent::Position(ref p) => p.id,
+                    PartyUpdateEvent::Full(ref p) => p.partial.id,
+                },
+                ServerMsg::PartyDelete(p) => p.id,
+
+                ServerMsg::RoleCreate(r) => r.party_id,
+                ServerMsg::RoleUpdate(r) => r.party_id,
+                ServerMsg::RoleDelete(r) => r.party_id,
+
+                ServerMsg::MemberAdd(e) => e.party_id,
+                ServerMsg::MemberUpdate(e) => e.party_id,
+                ServerMsg::MemberRemove(e) => e.party_id,
+                ServerMsg::MemberBan(e) => e.party_id,
+                ServerMsg::MemberUnban(e) => e.party_id,
+
+                ServerMsg::RoomCreate(r) => return r.inner.party_id,
+                ServerMsg::RoomUpdate(r) => return r.inner.party_id,
+                ServerMsg::RoomDelete(r) => return r.party_id,
+
+                ServerMsg::MessageCreate(m) => return m.party_id,
+                ServerMsg::MessageUpdate(m) => return m.party_id,
+                ServerMsg::MessageDelete(m) => return m.party_id,
+
+                ServerMsg::MessageReactionAdd(r) => return r.party_id,
+                ServerMsg::MessageReactionRemove(r) => return r.party_id,
+
+                ServerMsg::PresenceUpdate(p) => return p.party_id,
+                ServerMsg::TypingStart(t) => return t.party_id,
+                ServerMsg::ProfileUpdate(p) => return p.party_id,
+                _ => return None,
+            })
+        }
+
+        /// If the event relates to a specific room, get its ID
+        pub fn room_id(&self) -> Option<Snowflake> {
+            Some(match self {
+                ServerMsg::RoomCreate(r) => r.inner.id,
+                ServerMsg::RoomUpdate(r) => r.inner.id,
+                ServerMsg::RoomDelete(r) => r.id,
+
+                ServerMsg::MessageCreate(m) => m.room_id,
+                ServerMsg::MessageUpdate(m) => m.room_id,
+                ServerMsg::MessageDelete(m) => m.room_id,
+
+                ServerMsg::MessageReactionAdd(r) => r.room_id,
+                ServerMsg::MessageReactionRemove(r) => r.room_id,
+
+                ServerMsg::TypingStart(t) => t.room_id,
+                _ => return None,
+            })
+        }
+    }
+
+    #[cfg(feature = "framework")]
+    impl<C, U, S> DynamicServerMsgHandlers<C, U, S> {
+        /// Minimal intents required to receive every event with a registered handler.
+        ///
+        /// Events only passed to the fallback handler are not taken into account.
+        pub fn matching_intents(&self) -> Intent {
+            self.handled_opcodes().into_iter().filter_map(ServerMsgOpcode::matching_intent).collect()
+        }
     }
 
     #[cfg(test)]
@@ -801,10 +1099,91 @@ pub mod message {
         use std::mem::size_of;
 
         use super::*;
+        use crate::models::RoomFlags;
 
         #[test]
         fn test_client_msg_size() {
             assert_eq!(16, size_of::<ClientMsg>());
         }
+
+        #[test]
+        fn test_unknown_opcode() {
+            let msg: ServerMsg = serde_json::from_str(r#"{"o":200,"p":{"a":[1,2]},"x":true}"#).unwrap();
+
+            assert!(matches!(msg.opcode(), ServerMsgOpcode::Unknown));
+            assert_eq!(msg.raw_opcode(), 200);
+            assert!(matches!(msg, ServerMsg::Unknown(ref p) if matches!(p.payload, RawPayload::Json(ref v) if v["a"][1] == 2)));
+            assert_eq!(serde_json::to_string(&msg).unwrap(), r#"{"o":200,"p":{"a":[1,2]}}"#);
+
+            // fields added in newer versions are skipped for known messages too
+            let msg: ServerMsg = serde_json::from_str(r#"{"o":1,"x":0,"p":{"y":0},"z":[]}"#).unwrap();
+            assert!(matches!(msg, ServerMsg::HeartbeatAck(_)));
+
+            let msg: ServerMsg = serde_json::from_str(r#"[201]"#).unwrap();
+            assert!(matches!(msg, ServerMsg::Unknown(ref p) if p.payload.is_null()));
+            assert_eq!(serde_json::to_string(&msg).unwrap(), r#"{"o":201}"#);
+        }
+
+        #[test]
+        fn test_room_create() {
+            let msg: ServerMsg = serde_json::from_str(
+                r#"{"o":15,"p":{"id":20,"flags":48,"party_id":2,"avatar":null,"name":"general","position":1}}"#,
+            )
+            .unwrap();
+
+            let ServerMsg::RoomCreate(payload) = msg else {
+                panic!("expected RoomCreate, got {msg:?}");
+            };
+
+            assert_eq!(payload.id, "20".parse().unwrap());
+            assert_eq!(payload.party_id, Some("2".parse().unwrap()));
+            assert_eq!(payload.name, "general");
+            assert_eq!(payload.position, 1);
+            assert_eq!(payload.flags, RoomFlags::NSFW | RoomFlags::DEFAULT);
+            assert!(payload.overwrites.is_empty());
+
+            let msg: ServerMsg =
+                serde_json::from_str(r#"{"o":16,"p":{"id":20,"flags":0,"avatar":null,"name":"renamed","position":1}}"#).unwrap();
+            assert!(matches!(msg, ServerMsg::RoomUpdate(ref p) if p.name == "renamed" && p.party_id.is_none()));
+        }
+
+        #[cfg(feature = "cbor")]
+        #[test]
+        fn test_unknown_opcode_cbor() {
+            use ciborium::Value;
+
+            // byte strings and integer keys have no JSON representation
+            let payload = Value::Map(vec![
+                (Value::Integer(1.into()), Value::Bytes(vec![0, 1, 2])),
+                (Value::Text("a".into()), Value::Bool(true)),
+            ]);
+
+            let mut buf = Vec::new();
+            ciborium::ser::into_writer(&ServerMsg::new_unknown(200, payload.clone()), &mut buf).unwrap();
+
+            let msg: ServerMsg = ciborium::de::from_reader(&buf[..]).unwrap();
+
+            assert_eq!(msg.raw_opcode(), 200);
+            assert!(matches!(msg, ServerMsg::Unknown(ref p) if p.payload == RawPayload::Cbor(payload.clone())));
+
+            let mut reencoded = Vec::new();
+            ciborium::ser::into_writer(&msg, &mut reencoded).unwrap();
+            assert_eq!(buf, reencoded);
+        }
+
+        #[cfg(feature = "framework")]
+        #[test]
+        fn test_matching_intents() {
+            let mut handlers = DynamicServerMsgHandlers::<()>::default();
+            assert_eq!(handlers.matching_intents(), Intent::empty());
+
+            handlers
+                .on_hello(|_, _, _| async {})
+                .on_message_create(|_, _, _| async {})
+                .on_message_delete(|_, _, _| async {})
+                .on_typing_start(|_, _, _| async {});
+
+            assert_eq!(handlers.matching_intents(), Intent::MESSAGES | Intent::MESSAGE_TYPING);
+        }
     }
 }